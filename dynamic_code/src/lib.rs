@@ -6,44 +6,224 @@ use rune::{Module, Diagnostics, Source, Sources, Value, ContextError};
 use rune_modules::default_context;
 
 use once_cell::sync::Lazy;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 
 pub use rune;
 
-pub static MODULE_REGISTRY: Lazy<Mutex<Vec<Box<dyn Fn(&mut Module) -> Result<(), ContextError> + Send + Sync>>>> =
+pub type ModuleRegister = Arc<dyn Fn(&mut Module) -> Result<(), ContextError> + Send + Sync>;
+
+/// awaited by a run every so many vm instructions, see `DynamicCodeBuilder::yield_every`
+pub type YieldFn = Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()>>>>;
+
+/// one register of a module set with the crate its items are installed under
+#[derive(Clone)]
+pub struct ModuleEntry {
+    name: &'static str,
+    crate_name: Option<&'static str>,
+    deterministic: bool,
+    register: ModuleRegister,
+}
+
+impl ModuleEntry {
+    /// items land in the root, so scripts call them without a path
+    pub fn new<F>(name: &'static str, register: F) -> Self
+    where
+        F: Fn(&mut Module) -> Result<(), ContextError> + Send + Sync + 'static,
+    {
+        Self { name, crate_name: None, deterministic: true, register: Arc::new(register) }
+    }
+
+    /// install the items under `crate_name`, e.g. `gpu::batch` for a function named `batch`
+    pub fn in_crate(mut self, crate_name: &'static str) -> Self {
+        self.crate_name = Some(crate_name);
+        self
+    }
+
+    /// leave the entry out of deterministic builds, for items whose value differs
+    /// between workers such as device names or timings
+    pub fn nondeterministic(mut self) -> Self {
+        self.deterministic = false;
+        self
+    }
+}
+
+/// default module set, used by every `DynamicCodeBuilder` that is not given an explicit one
+pub static MODULE_REGISTRY: Lazy<Mutex<Vec<ModuleEntry>>> =
     Lazy::new(|| Mutex::new(Vec::new()));
 
+/// add an entry to the default set, replacing any earlier one with the same name
+pub fn register_entry(entry: ModuleEntry) {
+    let mut registry = MODULE_REGISTRY.lock().unwrap();
+
+    match registry.iter_mut().find(|e| e.name == entry.name) {
+        Some(existing) => *existing = entry,
+        None => registry.push(entry),
+    }
+}
+
+/// add a register to the default set, its items are installed in the root
+pub fn register_module<F>(name: &'static str, register: F)
+where
+    F: Fn(&mut Module) -> Result<(), ContextError> + Send + Sync + 'static,
+{
+    register_entry(ModuleEntry::new(name, register));
+}
+
+/// snapshot of the default module set
+pub fn default_modules() -> Vec<ModuleEntry> {
+    MODULE_REGISTRY.lock().unwrap().clone()
+}
+
 pub fn register_rust_function_i64(name: &'static str, func: fn(i64) -> i64) {
-    let register = Box::new(move |module: &mut Module| {
+    register_module(name, move |module: &mut Module| {
         module.function([name], func).build()?;
         Ok(())
     });
-    MODULE_REGISTRY.lock().unwrap().push(register);
 }
 
-pub fn register_rust_function_matrix(name: &'static str, func: fn(Vec<Vec<f32>>, Vec<Vec<f32>>) -> Vec<Vec<f32>>) {
-    let register = Box::new(move |module: &mut Module| {
+pub type MatrixFunction = fn(Vec<Vec<f32>>, Vec<Vec<f32>>) -> Vec<Vec<f32>>;
+
+pub fn register_rust_function_matrix(name: &'static str, func: MatrixFunction) {
+    register_module(name, move |module: &mut Module| {
         module.function([name], func).build()?;
         Ok(())
     });
-    MODULE_REGISTRY.lock().unwrap().push(register);
+}
+
+/// splitmix64, small and identical on every platform so seeded scripts replay bit for bit
+struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn install_rng(module: &mut Module, seed: u64) -> Result<(), ContextError> {
+    let rng = Arc::new(Mutex::new(SeededRng::new(seed)));
+
+    let rng_u64 = rng.clone();
+    module.function(["rand_u64"], move || rng_u64.lock().unwrap().next_u64() as i64).build()?;
+
+    let rng_f64 = rng.clone();
+    module.function(["rand_f64"], move || rng_f64.lock().unwrap().next_f64()).build()?;
+
+    module.function(["rand_seed"], move |seed: i64| {
+        *rng.lock().unwrap() = SeededRng::new(seed as u64);
+    }).build()?;
+
+    Ok(())
 }
 
 pub struct DynamicCode {
     vm: Vm,
     have_init: bool,
+    deterministic: bool,
+    yielder: Option<(usize, YieldFn)>,
 }
 
-impl DynamicCode {
-    pub fn new(script: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut context = default_context()?;
+/// deterministic builds get `rune`'s core modules instead of `rune_modules`, drop the
+/// entries marked `nondeterministic` and replace the rng with one seeded by the caller,
+/// so two workers given the same script, input and seed compute the same value.
+/// objects iterate in key order and are safe, `std::collections::HashMap` and `HashSet`
+/// hash with keys drawn once per process and iterate in a different order on every worker,
+/// they stay available since the prelude comes with the same core modules, so
+/// deterministic scripts must not depend on their iteration order.
+#[derive(Default)]
+pub struct DynamicCodeBuilder {
+    modules: Option<Vec<ModuleEntry>>,
+    seed: Option<u64>,
+    yielder: Option<(usize, YieldFn)>,
+}
+
+impl DynamicCodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// use exactly this module set instead of the default registry
+    pub fn modules(mut self, modules: Vec<ModuleEntry>) -> Self {
+        self.modules = Some(modules);
+        self
+    }
+
+    /// add one register to the explicit module set
+    pub fn module<F>(mut self, register: F) -> Self
+    where
+        F: Fn(&mut Module) -> Result<(), ContextError> + Send + Sync + 'static,
+    {
+        self.modules.get_or_insert_with(Vec::new).push(ModuleEntry::new("", register));
+        self
+    }
+
+    /// run in deterministic mode with a rng seeded from `seed`
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// step runs of `use_func_dyn` and await `yielder` every `instructions` vm instructions,
+    /// a script that never awaits still hands control back to the executor there, so the run
+    /// can be dropped (cancelled) and other tasks get polled
+    pub fn yield_every<F, Fut>(mut self, instructions: usize, yielder: F) -> Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.yielder = Some((instructions.max(1), Box::new(move || Box::pin(yielder()))));
+        self
+    }
+
+    pub fn build(self, script: &str) -> Result<DynamicCode, Box<dyn std::error::Error>> {
+        let deterministic = self.seed.is_some();
+
+        // rune_modules brings in time, rand and io which differ between runs
+        let mut context = if deterministic {
+            rune::Context::with_default_modules()?
+        } else {
+            default_context()?
+        };
+
+        let mut modules = self.modules.unwrap_or_else(default_modules);
+        if deterministic {
+            modules.retain(|entry| entry.deterministic);
+        }
 
         let mut module = Module::new();
-        for reg in MODULE_REGISTRY.lock().unwrap().iter() {
-            reg(&mut module)?;
+        let mut crates = Vec::new();
+        for entry in modules.iter() {
+            match entry.crate_name {
+                None => (entry.register)(&mut module)?,
+                Some(name) => {
+                    let mut crate_module = Module::with_crate(name)?;
+                    (entry.register)(&mut crate_module)?;
+                    crates.push(crate_module);
+                }
+            }
         }
+
+        install_rng(&mut module, self.seed.unwrap_or_else(public::rand_u64))?;
         
         context.install(module)?;
+        for crate_module in crates {
+            context.install(crate_module)?;
+        }
 
         let mut sources = Sources::new();
         let _ = sources.insert(Source::new("main", script).expect("invalid source"));
@@ -73,10 +253,26 @@ impl DynamicCode {
         let vm = Vm::new(runtime_context, Arc::new(unit));
 
         Ok(DynamicCode {
-            vm: vm,
+            vm,
             have_init: true,
+            deterministic,
+            yielder: self.yielder,
         })
     }
+}
+
+impl DynamicCode {
+    pub fn new(script: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        DynamicCodeBuilder::new().build(script)
+    }
+
+    pub fn builder() -> DynamicCodeBuilder {
+        DynamicCodeBuilder::new()
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
 
     pub fn use_func<T, A>(
         &mut self,
@@ -109,6 +305,26 @@ impl DynamicCode {
             _ => vec![json_to_rune(&json)?],
         };
 
+        if let Some((every, yielder)) = &self.yielder {
+            // a run dropped at a yield leaves its frames behind
+            self.vm.clear();
+            let mut execution = self.vm.execute([func], args)?;
+            let mut steps = 0;
+            let output = loop {
+                if let Some(output) = execution.async_step().await.into_result()? {
+                    break output;
+                }
+                steps += 1;
+                if steps % every == 0 {
+                    yielder().await;
+                }
+            };
+            return match T::from_value(output) {
+                VmResult::Ok(v) => Ok(v),
+                VmResult::Err(e) => Err(e.into()),
+            };
+        }
+
         let output = match args.len() {
             // 0 => self.vm.call([func], ())?,
             // 1 => self.vm.call([func], (args[0].clone(),))?,
@@ -168,4 +384,57 @@ fn json_to_rune(value: &serde_json::Value) -> Result<Value, Box<dyn std::error::
         serde_json::Value::Object(_obj) => return Err("Objects are not supported".into()),
         // _ => unreachable!("Unexpected Value variant"),
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::task::{Context, Poll, Waker};
+
+    const SCRIPT: &str = "pub async fn sum(n) { let s = 0; for i in 0..n { s += i; } s }";
+
+    // pending once, like a timer the host executor resolves later
+    async fn yield_once() {
+        let mut yielded = false;
+        std::future::poll_fn(|_| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            Poll::Pending
+        })
+        .await
+    }
+
+    #[test]
+    fn a_run_that_never_awaits_yields_and_can_be_dropped() {
+        let yields = Rc::new(Cell::new(0));
+        let counter = yields.clone();
+        let mut code = DynamicCode::builder()
+            .modules(Vec::new())
+            .yield_every(1000, move || {
+                counter.set(counter.get() + 1);
+                yield_once()
+            })
+            .build(SCRIPT)
+            .unwrap();
+
+        let mut cx = Context::from_waker(Waker::noop());
+        {
+            let mut run = Box::pin(code.use_func_dyn::<i64>("sum", "[1000000000]"));
+            assert!(run.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(yields.get(), 1);
+        }
+
+        let mut run = Box::pin(code.use_func_dyn::<i64>("sum", "[100000]"));
+        let sum = loop {
+            if let Poll::Ready(sum) = run.as_mut().poll(&mut cx) {
+                break sum.unwrap();
+            }
+        };
+        assert_eq!(sum, 4999950000);
+        assert!(yields.get() > 1);
+    }
+}
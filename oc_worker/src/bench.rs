@@ -0,0 +1,210 @@
+use serde::Serialize;
+
+use crate::compute_backend::{Backend, ComputeBackend};
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init_rune_func::get_backend;
+use dynamic_code::DynamicCode;
+
+// every measurement repeats until it has run at least this long
+const MIN_BENCH_MS: f64 = 100.0;
+// floats moved by one upload / readback when measuring latency
+const LATENCY_LEN: usize = 256;
+// bytes copied per pass when measuring bandwidth, smaller on the cpu to spare wasm memory
+const BANDWIDTH_BYTES: u64 = 64 << 20;
+const CPU_BANDWIDTH_BYTES: u64 = 16 << 20;
+// loop iterations per rune call
+const RUNE_LOOP: i64 = 100_000;
+
+const RUNE_BENCH_SCRIPT: &str = r#"
+    pub fn bench_loop(n) {
+        let sum = 0;
+        for i in 0..n {
+            sum += i % 7;
+        }
+        sum
+    }
+"#;
+
+/// sent with worker/bench, the scheduler derives its weights from these
+#[derive(Debug, Default, Serialize)]
+pub struct BenchReport {
+    pub backend: String,
+    pub matmul_size: u32,
+    pub matmul_gflops: f64,
+    /// read + write bytes per second of a device side copy
+    pub bandwidth_gbps: f64,
+    pub upload_ms: f64,
+    pub readback_ms: f64,
+    /// million rune loop iterations per second
+    pub rune_mips: f64,
+    pub total_ms: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+/// wait until the gpu finished everything writing `id`, no-op on the cpu
+async fn sync(backend: &Backend, id: u64) -> Result<(), GpuError> {
+    if let Backend::Gpu(gpu) = backend {
+        let (buffer, _) = gpu.tensor(id, DType::F32)?;
+        gpu.read_back_bytes(&buffer, 4).await?;
+    }
+    Ok(())
+}
+
+async fn bench_matmul(backend: &Backend, n: u32) -> Result<f64, GpuError> {
+    let data: Vec<f32> = (0..n * n).map(|i| (i % 13) as f32 * 0.25).collect();
+    let a = backend.tensor_upload(&data, vec![n, n])?;
+    let b = backend.tensor_upload(&data, vec![n, n])?;
+
+    // warm up, also compiles anything lazily compiled
+    let warm = backend.tensor_matmul(a, b).await?;
+    sync(backend, warm).await?;
+    backend.tensor_free(warm)?;
+
+    let start = now_ms();
+    let mut runs = 0u32;
+    while now_ms() - start < MIN_BENCH_MS {
+        let c = backend.tensor_matmul(a, b).await?;
+        sync(backend, c).await?;
+        backend.tensor_free(c)?;
+        runs += 1;
+    }
+    let elapsed = (now_ms() - start).max(1.0);
+
+    backend.tensor_free(a)?;
+    backend.tensor_free(b)?;
+
+    let flops = 2.0 * (n as f64).powi(3) * runs as f64;
+    Ok(flops / (elapsed / 1000.0) / 1e9)
+}
+
+async fn bench_bandwidth(backend: &Backend) -> Result<f64, GpuError> {
+    let mut passes = 0u64;
+    let start;
+    let bytes;
+
+    match backend {
+        Backend::Gpu(gpu) => {
+            let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+            let src = gpu.acquire_buffer(BANDWIDTH_BYTES, usage)?;
+            let dst = gpu.acquire_buffer(BANDWIDTH_BYTES, usage)?;
+
+            bytes = BANDWIDTH_BYTES;
+            start = now_ms();
+            while now_ms() - start < MIN_BENCH_MS {
+                gpu.scoped(|| {
+                    let mut encoder = gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                    encoder.copy_buffer_to_buffer(&src, 0, &dst, 0, BANDWIDTH_BYTES);
+                    gpu.queue.submit(Some(encoder.finish()));
+                    Ok(())
+                })
+                .await?;
+                gpu.read_back_bytes(&dst, 4).await?;
+                passes += 1;
+            }
+        }
+        Backend::Cpu(_) => {
+            bytes = CPU_BANDWIDTH_BYTES;
+            let src = vec![1u8; bytes as usize];
+            let mut dst = vec![0u8; bytes as usize];
+
+            start = now_ms();
+            while now_ms() - start < MIN_BENCH_MS {
+                dst.copy_from_slice(&src);
+                std::hint::black_box(&mut dst);
+                passes += 1;
+            }
+        }
+    }
+
+    let elapsed = (now_ms() - start).max(1.0);
+    Ok((2 * bytes * passes) as f64 / (elapsed / 1000.0) / 1e9)
+}
+
+/// average ms of a small upload and of a small readback
+async fn bench_latency(backend: &Backend) -> Result<(f64, f64), GpuError> {
+    let data = vec![1.0f32; LATENCY_LEN];
+
+    let start = now_ms();
+    let mut uploads = 0u32;
+    while now_ms() - start < MIN_BENCH_MS {
+        let id = backend.tensor_upload(&data, vec![LATENCY_LEN as u32])?;
+        sync(backend, id).await?;
+        backend.tensor_free(id)?;
+        uploads += 1;
+    }
+    let upload_ms = (now_ms() - start) / uploads as f64;
+
+    let id = backend.tensor_upload(&data, vec![LATENCY_LEN as u32])?;
+    let start = now_ms();
+    let mut readbacks = 0u32;
+    while now_ms() - start < MIN_BENCH_MS {
+        backend.tensor_download(id).await?;
+        readbacks += 1;
+    }
+    let readback_ms = (now_ms() - start) / readbacks as f64;
+    backend.tensor_free(id)?;
+
+    Ok((upload_ms, readback_ms))
+}
+
+fn bench_rune() -> Result<f64, Box<dyn std::error::Error>> {
+    let mut code = DynamicCode::new(RUNE_BENCH_SCRIPT)?;
+
+    let start = now_ms();
+    let mut calls = 0u32;
+    while now_ms() - start < MIN_BENCH_MS {
+        code.use_func::<i64, (i64,)>("bench_loop", (RUNE_LOOP,))?;
+        calls += 1;
+    }
+    let elapsed = (now_ms() - start).max(1.0);
+
+    Ok((RUNE_LOOP as f64 * calls as f64) / (elapsed / 1000.0) / 1e6)
+}
+
+/// run the whole suite, a failing part is reported in `errors` and leaves its score at 0
+pub async fn run_bench() -> BenchReport {
+    let start = now_ms();
+    let mut report = BenchReport::default();
+
+    match get_backend() {
+        Ok(backend) => {
+            report.backend = backend.name().to_string();
+            // a single wasm thread needs far too long for the gpu size
+            report.matmul_size = match backend {
+                Backend::Gpu(_) => 512,
+                Backend::Cpu(_) => 128,
+            };
+
+            match bench_matmul(&backend, report.matmul_size).await {
+                Ok(gflops) => report.matmul_gflops = gflops,
+                Err(e) => report.errors.push(format!("matmul: {}", e)),
+            }
+            match bench_bandwidth(&backend).await {
+                Ok(gbps) => report.bandwidth_gbps = gbps,
+                Err(e) => report.errors.push(format!("bandwidth: {}", e)),
+            }
+            match bench_latency(&backend).await {
+                Ok((upload_ms, readback_ms)) => {
+                    report.upload_ms = upload_ms;
+                    report.readback_ms = readback_ms;
+                }
+                Err(e) => report.errors.push(format!("latency: {}", e)),
+            }
+        }
+        Err(e) => report.errors.push(e.to_string()),
+    }
+
+    match bench_rune() {
+        Ok(mips) => report.rune_mips = mips,
+        Err(e) => report.errors.push(format!("rune: {}", e)),
+    }
+
+    report.total_ms = now_ms() - start;
+    report
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use wasm_bindgen::JsValue;
+
+use crate::compute_backend::{Backend, ComputeBackend};
+use crate::gpu_init::GpuManager;
+use crate::gpu_init_rune_func::get_backend;
+use crate::gpu_schedule::devices;
+
+#[derive(Debug, Serialize)]
+pub struct AdapterReport {
+    pub name: String,
+    pub vendor: u32,
+    pub device: u32,
+    pub device_type: String,
+    pub backend: String,
+    pub driver: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitsReport {
+    pub max_buffer_size: u64,
+    pub max_storage_buffer_binding_size: u32,
+    pub max_compute_workgroup_size_x: u32,
+    pub max_compute_workgroup_size_y: u32,
+    pub max_compute_workgroup_size_z: u32,
+    pub max_compute_invocations_per_workgroup: u32,
+    pub max_compute_workgroup_storage_size: u32,
+    pub max_compute_workgroups_per_dimension: u32,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FeatureReport {
+    pub shader_f16: bool,
+    pub timestamp_query: bool,
+    pub subgroup: bool,
+}
+
+/// payload of worker/hello, lets the scheduler place jobs on suitable workers
+#[derive(Debug, Serialize)]
+pub struct CapabilityReport {
+    pub worker_version: &'static str,
+    /// "gpu", "cpu" or "none" while the backend is still starting
+    pub backend: &'static str,
+    pub adapter: Option<AdapterReport>,
+    /// further devices runs are spread over, native with `multi_adapter` only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_adapters: Vec<AdapterReport>,
+    pub limits: Option<LimitsReport>,
+    pub features: FeatureReport,
+    pub cpu_cores: Option<u32>,
+    /// browsers round this down and cap it at 8
+    pub memory_gb: Option<f64>,
+}
+
+fn adapter_report(gpu: &GpuManager) -> AdapterReport {
+    let info = &gpu.adapter_info;
+    AdapterReport {
+        name: info.name.clone(),
+        vendor: info.vendor,
+        device: info.device,
+        device_type: format!("{:?}", info.device_type),
+        backend: format!("{:?}", info.backend),
+        driver: info.driver.clone(),
+    }
+}
+
+fn limits_report(gpu: &GpuManager) -> LimitsReport {
+    let limits = gpu.device.limits();
+    LimitsReport {
+        max_buffer_size: limits.max_buffer_size,
+        max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+        max_compute_workgroup_size_x: limits.max_compute_workgroup_size_x,
+        max_compute_workgroup_size_y: limits.max_compute_workgroup_size_y,
+        max_compute_workgroup_size_z: limits.max_compute_workgroup_size_z,
+        max_compute_invocations_per_workgroup: limits.max_compute_invocations_per_workgroup,
+        max_compute_workgroup_storage_size: limits.max_compute_workgroup_storage_size,
+        max_compute_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+    }
+}
+
+fn feature_report(gpu: &GpuManager) -> FeatureReport {
+    let features = gpu.device.features();
+    FeatureReport {
+        shader_f16: features.contains(wgpu::Features::SHADER_F16),
+        timestamp_query: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+        subgroup: features.contains(wgpu::Features::SUBGROUP),
+    }
+}
+
+/// number field of `navigator`, works in a window and in a web worker
+fn navigator_number(field: &str) -> Option<f64> {
+    let navigator = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("navigator")).ok()?;
+    js_sys::Reflect::get(&navigator, &JsValue::from_str(field)).ok()?.as_f64()
+}
+
+pub fn capability_report() -> CapabilityReport {
+    let backend = get_backend().ok();
+    let gpu = match backend.as_ref() {
+        Some(Backend::Gpu(gpu)) => Some(gpu),
+        _ => None,
+    };
+
+    CapabilityReport {
+        worker_version: env!("CARGO_PKG_VERSION"),
+        backend: backend.as_ref().map_or("none", |b| b.name()),
+        adapter: gpu.map(adapter_report),
+        extra_adapters: devices().iter().skip(1).map(adapter_report).collect(),
+        limits: gpu.map(limits_report),
+        features: gpu.map(feature_report).unwrap_or_default(),
+        cpu_cores: navigator_number("hardwareConcurrency").map(|n| n as u32),
+        memory_gb: navigator_number("deviceMemory"),
+    }
+}
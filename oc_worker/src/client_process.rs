@@ -3,26 +3,43 @@ use crate::{protocol::BaseMsg};
 use public::{parse_json, decode};
 
 use crate::{protocol};
+use crate::gpu_init_rune_func::{recover_lost_devices, release_gpu_buffers, save_pipeline_caches, set_gpu_deterministic, try_get_gpu};
+use crate::gpu_kernel::KernelSource;
+use crate::gpu_schedule::{devices, lease_device};
+use crate::progress;
+use crate::bench;
+use crate::result_hash::{canonical_hash, canonical_json_hash, sha256_hex};
 use dynamic_code::{DynamicCode};
 use serde::{Deserialize, Serialize};
+use futures::future::{AbortHandle, Abortable};
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 
 
+// vm instructions a run executes before it lets the event loop in, a cancel message
+// can only be handled then
+const RUN_SLICE: usize = 1 << 20;
+
 thread_local! {
     pub static DYNC_CODE: Rc<RefCell<Option<DynamicCode>>> = Rc::new(RefCell::new(None));
+    static SCRIPT_HASH: RefCell<String> = const { RefCell::new(String::new()) };
+    // event_id -> handle of the worker/run still executing for it, removed by the run itself
+    // once its future is gone, so an entry means the run may still hold the vm
+    static RUNNING_TASKS: RefCell<HashMap<u64, AbortHandle>> = RefCell::new(HashMap::new());
 }
 
 pub async fn worker_hello(code: i16, _payload: String){
 	if code == 0{
-		web_sys::console::log_1(&format!("worker hello succ").into());
+		web_sys::console::log_1(&"worker hello succ".into());
 	}else{
-		web_sys::console::log_1(&format!("worker hello failed").into());
+		web_sys::console::log_1(&"worker hello failed".into());
 	}
 }
 
 pub async fn worker_init(_code: i16, payload: String, big_payload: String){
+	recover_lost_devices().await;
 
 	match parse_json::<BaseMsg>(&payload){
 		Ok(mut base_msg) => {
@@ -34,12 +51,27 @@ pub async fn worker_init(_code: i16, payload: String, big_payload: String){
                     let init_code = decode(&big_payload, base_msg.event_id);
                     let init_code: String = parse_json(&init_code).unwrap();
 
-					match DynamicCode::new(&init_code){
+					let mut builder = DynamicCode::builder().yield_every(RUN_SLICE, || crate::sleep_ms(0));
+					if init_code_payload.deterministic {
+						builder = builder.deterministic(init_code_payload.seed);
+					}
+					set_gpu_deterministic(init_code_payload.deterministic);
+
+					if let Err(e) = load_kernels(&init_code_payload.kernels).await {
+						web_sys::console::log_1(&format!("load kernel failed {:?}", e).into());
+						protocol::worker_init(base_msg.event_id, init_code_payload.source_uid, false, e);
+						return;
+					}
+
+					match builder.build(&init_code){
 						Ok(dcm) => {
 							DYNC_CODE.with(|code| {
 							    code.borrow_mut().replace(dcm);
 							});
-                            web_sys::console::log_1(&format!("init code succ").into());
+							SCRIPT_HASH.with(|hash| {
+							    *hash.borrow_mut() = sha256_hex(init_code.as_bytes());
+							});
+                            web_sys::console::log_1(&"init code succ".into());
 							protocol::worker_init(base_msg.event_id, init_code_payload.source_uid, true, "".to_string());
 						},
 						Err(e) => {
@@ -48,31 +80,62 @@ pub async fn worker_init(_code: i16, payload: String, big_payload: String){
                         },
 					}
 				},
-				Err(e) => web_sys::console::log_1(&format!("parse InitCodePayload msg error:{}", e.to_string()).into())
+				Err(e) => web_sys::console::log_1(&format!("parse InitCodePayload msg error:{}", e).into())
 			};
 		},
-		Err(e) => web_sys::console::log_1(&format!("parse base msg error:{}", e.to_string()).into()),
+		Err(e) => web_sys::console::log_1(&format!("parse base msg error:{}", e).into()),
 	};
 }
 
+async fn load_kernels(kernels: &[KernelSource]) -> Result<(), String> {
+	if kernels.is_empty() {
+		return Ok(());
+	}
+
+	let gpus = devices();
+	if gpus.is_empty() {
+		return Err("gpu not available for runtime kernels".to_string());
+	}
+	// runs may land on any device, each needs every kernel
+	for gpu in gpus {
+		for kernel in kernels {
+			gpu.load_kernel(kernel).await?;
+		}
+	}
+	save_pipeline_caches();
+	Ok(())
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 struct DynamicRunCodeInfo{
 	source_uid	: String,
 	func	: String,
 	output	: i16,
+	// also commit to the input and the loaded script
+	#[serde(default)]
+	commit	: bool,
+	// send only the hashes, for redundancy checks across workers
+	#[serde(default)]
+	hash_only	: bool,
+	// time the gpu work of this run, sent back as metrics
+	#[serde(default)]
+	profile	: bool,
 }
 
 macro_rules! call_and_send {
     ($manager:expr, $ty:ty, $payload:expr, $payload_2:expr) => {
         match $manager.use_func_dyn::<$ty>($payload, $payload_2).await {
-            Ok(result) => (format!("{:?}", result), "".to_string()),
-            Err(e) => ("".to_string(), e.to_string()),
+            Ok(result) => match canonical_hash(&result) {
+                Ok(hash) => (format!("{:?}", result), hash, "".to_string()),
+                Err(e) => ("".to_string(), "".to_string(), e),
+            },
+            Err(e) => ("".to_string(), "".to_string(), e.to_string()),
         }
     };
 }
 
 pub async fn worker_run(_code: i16, payload: String, big_payload: String) {
+    recover_lost_devices().await;
     match parse_json::<BaseMsg>(&payload) {
         Ok(mut base_msg) => {
             let msg_info = base_msg.get_msg();
@@ -90,27 +153,146 @@ pub async fn worker_run(_code: i16, payload: String, big_payload: String) {
                 let input = decode(&big_payload, base_msg.event_id);
                 let input: String = parse_json(&input).unwrap();
 
-                let (result, error) = match call_func.output {
-                    1 => call_and_send!(c, i32, &call_func.func, &input),
-                    2 => call_and_send!(c, f32, &call_func.func, &input),
-                    3 => call_and_send!(c, String, &call_func.func, &input),
-                    4 => call_and_send!(c, Vec<i32>, &call_func.func, &input),
-                    5 => call_and_send!(c, Vec<f32>, &call_func.func, &input),
-                    6 => call_and_send!(c, Vec<Vec<i32>>, &call_func.func, &input),
-                    7 => call_and_send!(c, Vec<Vec<f32>>, &call_func.func, &input),
-                    _ => ("".to_string(), "no support this type".to_string()),
+                if RUNNING_TASKS.with(|tasks| tasks.borrow().contains_key(&base_msg.event_id)) {
+                    protocol::worker_run(base_msg.event_id, msg_info.operator_id, call_func.source_uid, "".to_string(), "event id already running".to_string(), protocol::RunCodeHashes::default(), None);
+                    return;
+                }
+
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                RUNNING_TASKS.with(|tasks| tasks.borrow_mut().insert(base_msg.event_id, abort_handle));
+                progress::begin_run(base_msg.event_id, msg_info.operator_id);
+                // with several devices the run gets the least busy one
+                let lease = lease_device();
+                let profiled = match &lease {
+                    _ if !call_func.profile => None,
+                    Some(lease) => Some(lease.gpu().clone()),
+                    None => try_get_gpu(),
                 };
+                if let Some(gpu) = &profiled {
+                    gpu.begin_profile(base_msg.event_id);
+                }
 
-                protocol::worker_run(base_msg.event_id, msg_info.operator_id, call_func.source_uid, result, error);
+                // the rune call can only be stopped where it awaits (gpu readback, other async fns,
+                // the yield every RUN_SLICE instructions), dropping it there also drops the pending
+                // readback and its buffers
+                let task = progress::in_run(base_msg.event_id, Abortable::new(async {
+                    match call_func.output {
+                        1 => call_and_send!(c, i32, &call_func.func, &input),
+                        2 => call_and_send!(c, f32, &call_func.func, &input),
+                        3 => call_and_send!(c, String, &call_func.func, &input),
+                        4 => call_and_send!(c, Vec<i32>, &call_func.func, &input),
+                        5 => call_and_send!(c, Vec<f32>, &call_func.func, &input),
+                        6 => call_and_send!(c, Vec<Vec<i32>>, &call_func.func, &input),
+                        7 => call_and_send!(c, Vec<Vec<f32>>, &call_func.func, &input),
+                        _ => ("".to_string(), "".to_string(), "no support this type".to_string()),
+                    }
+                }, abort_registration));
+                let run = match &lease {
+                    Some(lease) => lease.run(task).await,
+                    None => task.await,
+                };
+                drop(lease);
+
+                RUNNING_TASKS.with(|tasks| tasks.borrow_mut().remove(&base_msg.event_id));
+                progress::end_run(base_msg.event_id);
+                let metrics = match &profiled {
+                    Some(gpu) => Some(gpu.end_profile(base_msg.event_id).await),
+                    None => None,
+                };
+
+                let (result, result_hash, error) = match run {
+                    Ok(v) => v,
+                    Err(_) => ("".to_string(), "".to_string(), "cancelled".to_string()),
+                };
+
+                let mut hashes = protocol::RunCodeHashes { result_hash, ..Default::default() };
+                if call_func.commit {
+                    hashes.input_hash = Some(canonical_json_hash(&input));
+                    hashes.script_hash = Some(SCRIPT_HASH.with(|hash| hash.borrow().clone()));
+                }
+
+                let result = if call_func.hash_only { "".to_string() } else { result };
+
+                protocol::worker_run(base_msg.event_id, msg_info.operator_id, call_func.source_uid, result, error, hashes, metrics);
             } else {
-                protocol::worker_run(base_msg.event_id, msg_info.operator_id, "".to_string(), "".to_string(), "dync manager no exsis".to_string());
+                protocol::worker_run(base_msg.event_id, msg_info.operator_id, "".to_string(), "".to_string(), "dync manager no exsis".to_string(), protocol::RunCodeHashes::default(), None);
+            }
+        }
+        Err(e) => protocol::send_msg_to_verifier("worker/error".to_string(), e.to_string()),
+    }
+}
+
+
+pub async fn worker_cancel(_code: i16, payload: String){
+    match parse_json::<BaseMsg>(&payload) {
+        Ok(mut base_msg) => {
+            let msg_info = base_msg.get_msg();
+
+            match parse_json::<protocol::CancelPayload>(&msg_info.payload) {
+                Ok(cancel) => {
+                    // the run removes its entry once it has stopped
+                    let handle = RUNNING_TASKS.with(|tasks| tasks.borrow().get(&cancel.event_id).cloned());
+
+                    match handle {
+                        Some(handle) => {
+                            handle.abort();
+                            protocol::worker_cancel(base_msg.event_id, cancel.event_id, true, "".to_string());
+                        },
+                        None => protocol::worker_cancel(base_msg.event_id, cancel.event_id, false, "no running task".to_string()),
+                    }
+                },
+                Err(e) => protocol::worker_cancel(base_msg.event_id, 0, false, e.to_string()),
             }
         }
         Err(e) => protocol::send_msg_to_verifier("worker/error".to_string(), e.to_string()),
     }
 }
 
+pub async fn worker_close(_code: i16, payload: String){
+    match parse_json::<BaseMsg>(&payload) {
+        Ok(base_msg) => {
+            let cancelled = abort_runs().await;
+
+            DYNC_CODE.with(|code| {
+                code.borrow_mut().take();
+            });
+            SCRIPT_HASH.with(|hash| hash.borrow_mut().clear());
+            release_gpu_buffers();
+            // built-in kernels compiled during the runs
+            save_pipeline_caches();
 
-pub async fn worker_close(_code: i16, _payload: String){
+            protocol::worker_close(base_msg.event_id, true, format!("cancelled {} task", cancelled));
+        }
+        Err(e) => protocol::send_msg_to_verifier("worker/error".to_string(), e.to_string()),
+    }
+}
+
+/// abort every run and wait until all of them dropped their futures, which borrow the vm,
+/// runs that start while waiting are aborted as well, returns how many were aborted
+async fn abort_runs() -> usize {
+    let mut aborted = 0;
+    loop {
+        let handles: Vec<AbortHandle> = RUNNING_TASKS.with(|tasks| tasks.borrow().values().cloned().collect());
+        if handles.is_empty() {
+            return aborted;
+        }
+        for handle in handles {
+            if !handle.is_aborted() {
+                handle.abort();
+                aborted += 1;
+            }
+        }
+        // an aborted run finishes the next time the executor polls it
+        crate::sleep_ms(1).await;
+    }
 }
 
+pub async fn worker_bench(_code: i16, payload: String){
+    match parse_json::<BaseMsg>(&payload) {
+        Ok(base_msg) => {
+            let report = bench::run_bench().await;
+            protocol::worker_bench(base_msg.event_id, &report);
+        }
+        Err(e) => protocol::send_msg_to_verifier("worker/error".to_string(), e.to_string()),
+    }
+}
@@ -0,0 +1,401 @@
+use crate::cpu_backend::CpuBackend;
+use crate::gpu_conv::{Conv2dSpec, PoolMode};
+use crate::gpu_elementwise::ElementwiseOp;
+use crate::gpu_error::GpuError;
+use crate::gpu_gemm::GemmSpec;
+use crate::gpu_init::GpuManager;
+
+/// kernels every backend has to provide, rune scripts only see these through `Backend`
+#[allow(async_fn_in_trait)]
+pub trait ComputeBackend {
+    fn name(&self) -> &'static str;
+
+    async fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, GpuError>;
+    async fn matrix_multiply(&self, a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, GpuError>;
+    async fn vec_matrix_multiply(&self, a: Vec<f32>, b: Vec<Vec<f32>>) -> Result<Vec<f32>, GpuError>;
+    async fn gemm(&self, spec: &GemmSpec, a: &[f32], b: &[f32], c: Option<&[f32]>, bias: Option<&[f32]>) -> Result<Vec<f32>, GpuError>;
+
+    fn tensor_upload(&self, data: &[f32], shape: Vec<u32>) -> Result<u64, GpuError>;
+    async fn tensor_download(&self, id: u64) -> Result<Vec<f32>, GpuError>;
+    fn tensor_shape(&self, id: u64) -> Result<Vec<u32>, GpuError>;
+    fn tensor_retain(&self, id: u64) -> Result<(), GpuError>;
+    fn tensor_free(&self, id: u64) -> Result<(), GpuError>;
+    async fn tensor_add(&self, a: u64, b: u64) -> Result<u64, GpuError>;
+    async fn tensor_elementwise(&self, op: ElementwiseOp, inputs: &[u64], alpha: f32, beta: f32) -> Result<u64, GpuError>;
+    async fn tensor_matmul(&self, a: u64, b: u64) -> Result<u64, GpuError>;
+    async fn tensor_vec_matmul(&self, v: u64, m: u64) -> Result<u64, GpuError>;
+    async fn tensor_gemm(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError>;
+
+    async fn tensor_softmax(&self, id: u64) -> Result<u64, GpuError>;
+    async fn tensor_layer_norm(&self, id: u64, gamma: u64, beta: u64, eps: f32) -> Result<u64, GpuError>;
+    async fn tensor_rms_norm(&self, id: u64, gamma: u64, eps: f32) -> Result<u64, GpuError>;
+    async fn tensor_gelu(&self, id: u64) -> Result<u64, GpuError>;
+    async fn tensor_silu(&self, id: u64) -> Result<u64, GpuError>;
+    async fn tensor_rope(&self, id: u64, offset: u32, base: f32) -> Result<u64, GpuError>;
+    async fn tensor_attention(&self, q: u64, k: u64, v: u64, causal: bool) -> Result<u64, GpuError>;
+
+    async fn tensor_conv2d(&self, x: u64, weight: u64, bias: Option<u64>, spec: &Conv2dSpec) -> Result<u64, GpuError>;
+    async fn tensor_pool2d(&self, x: u64, mode: PoolMode, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError>;
+    async fn tensor_im2col(&self, x: u64, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError>;
+
+    async fn tensor_sum(&self, id: u64) -> Result<f32, GpuError>;
+    async fn tensor_mean(&self, id: u64) -> Result<f32, GpuError>;
+    async fn tensor_min(&self, id: u64) -> Result<f32, GpuError>;
+    async fn tensor_max(&self, id: u64) -> Result<f32, GpuError>;
+    async fn tensor_dot(&self, a: u64, b: u64) -> Result<f32, GpuError>;
+    async fn tensor_argmax(&self, id: u64) -> Result<u32, GpuError>;
+    async fn tensor_scan(&self, id: u64, exclusive: bool) -> Result<u64, GpuError>;
+
+    fn batch_new(&self) -> u64;
+    fn batch_add(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError>;
+    fn batch_matmul(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError>;
+    fn batch_vec_matmul(&self, batch: u64, v: u64, m: u64) -> Result<u64, GpuError>;
+    fn batch_read(&self, batch: u64, tensor: u64) -> Result<(), GpuError>;
+    async fn batch_submit(&self, batch: u64) -> Result<Vec<Vec<f32>>, GpuError>;
+
+    /// drop every resident tensor, e.g. when the worker closes
+    fn release_buffers(&self);
+}
+
+// inherent methods of GpuManager take precedence over the trait ones in these paths
+impl ComputeBackend for GpuManager {
+    fn name(&self) -> &'static str {
+        "gpu"
+    }
+
+    async fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, GpuError> {
+        GpuManager::add(self, a, b).await
+    }
+
+    async fn matrix_multiply(&self, a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, GpuError> {
+        GpuManager::matrix_multiply(self, a, b).await
+    }
+
+    async fn vec_matrix_multiply(&self, a: Vec<f32>, b: Vec<Vec<f32>>) -> Result<Vec<f32>, GpuError> {
+        GpuManager::vec_matrix_multiply(self, a, b).await
+    }
+
+    async fn gemm(&self, spec: &GemmSpec, a: &[f32], b: &[f32], c: Option<&[f32]>, bias: Option<&[f32]>) -> Result<Vec<f32>, GpuError> {
+        GpuManager::gemm(self, spec, a, b, c, bias).await
+    }
+
+    fn tensor_upload(&self, data: &[f32], shape: Vec<u32>) -> Result<u64, GpuError> {
+        GpuManager::tensor_upload(self, data, shape)
+    }
+
+    async fn tensor_download(&self, id: u64) -> Result<Vec<f32>, GpuError> {
+        GpuManager::tensor_download(self, id).await
+    }
+
+    fn tensor_shape(&self, id: u64) -> Result<Vec<u32>, GpuError> {
+        GpuManager::tensor_shape(self, id)
+    }
+
+    fn tensor_retain(&self, id: u64) -> Result<(), GpuError> {
+        GpuManager::tensor_retain(self, id)
+    }
+
+    fn tensor_free(&self, id: u64) -> Result<(), GpuError> {
+        GpuManager::tensor_free(self, id)
+    }
+
+    async fn tensor_add(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_add(self, a, b).await
+    }
+
+    async fn tensor_elementwise(&self, op: ElementwiseOp, inputs: &[u64], alpha: f32, beta: f32) -> Result<u64, GpuError> {
+        GpuManager::tensor_elementwise(self, op, inputs, alpha, beta).await
+    }
+
+    async fn tensor_matmul(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_matmul(self, a, b).await
+    }
+
+    async fn tensor_vec_matmul(&self, v: u64, m: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_vec_matmul(self, v, m).await
+    }
+
+    async fn tensor_gemm(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError> {
+        GpuManager::tensor_gemm(self, spec, a, b, c, bias).await
+    }
+
+    async fn tensor_softmax(&self, id: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_softmax(self, id).await
+    }
+
+    async fn tensor_layer_norm(&self, id: u64, gamma: u64, beta: u64, eps: f32) -> Result<u64, GpuError> {
+        GpuManager::tensor_layer_norm(self, id, gamma, beta, eps).await
+    }
+
+    async fn tensor_rms_norm(&self, id: u64, gamma: u64, eps: f32) -> Result<u64, GpuError> {
+        GpuManager::tensor_rms_norm(self, id, gamma, eps).await
+    }
+
+    async fn tensor_gelu(&self, id: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_gelu(self, id).await
+    }
+
+    async fn tensor_silu(&self, id: u64) -> Result<u64, GpuError> {
+        GpuManager::tensor_silu(self, id).await
+    }
+
+    async fn tensor_rope(&self, id: u64, offset: u32, base: f32) -> Result<u64, GpuError> {
+        GpuManager::tensor_rope(self, id, offset, base).await
+    }
+
+    async fn tensor_attention(&self, q: u64, k: u64, v: u64, causal: bool) -> Result<u64, GpuError> {
+        GpuManager::tensor_attention(self, q, k, v, causal).await
+    }
+
+    async fn tensor_conv2d(&self, x: u64, weight: u64, bias: Option<u64>, spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        GpuManager::tensor_conv2d(self, x, weight, bias, spec).await
+    }
+
+    async fn tensor_pool2d(&self, x: u64, mode: PoolMode, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        GpuManager::tensor_pool2d(self, x, mode, kernel, spec).await
+    }
+
+    async fn tensor_im2col(&self, x: u64, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        GpuManager::tensor_im2col(self, x, kernel, spec).await
+    }
+
+    async fn tensor_sum(&self, id: u64) -> Result<f32, GpuError> {
+        GpuManager::tensor_sum(self, id).await
+    }
+
+    async fn tensor_mean(&self, id: u64) -> Result<f32, GpuError> {
+        GpuManager::tensor_mean(self, id).await
+    }
+
+    async fn tensor_min(&self, id: u64) -> Result<f32, GpuError> {
+        GpuManager::tensor_min(self, id).await
+    }
+
+    async fn tensor_max(&self, id: u64) -> Result<f32, GpuError> {
+        GpuManager::tensor_max(self, id).await
+    }
+
+    async fn tensor_dot(&self, a: u64, b: u64) -> Result<f32, GpuError> {
+        GpuManager::tensor_dot(self, a, b).await
+    }
+
+    async fn tensor_argmax(&self, id: u64) -> Result<u32, GpuError> {
+        GpuManager::tensor_argmax(self, id).await
+    }
+
+    async fn tensor_scan(&self, id: u64, exclusive: bool) -> Result<u64, GpuError> {
+        GpuManager::tensor_scan(self, id, exclusive).await
+    }
+
+    fn batch_new(&self) -> u64 {
+        GpuManager::batch_new(self)
+    }
+
+    fn batch_add(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        GpuManager::batch_add(self, batch, a, b)
+    }
+
+    fn batch_matmul(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        GpuManager::batch_matmul(self, batch, a, b)
+    }
+
+    fn batch_vec_matmul(&self, batch: u64, v: u64, m: u64) -> Result<u64, GpuError> {
+        GpuManager::batch_vec_matmul(self, batch, v, m)
+    }
+
+    fn batch_read(&self, batch: u64, tensor: u64) -> Result<(), GpuError> {
+        GpuManager::batch_read(self, batch, tensor)
+    }
+
+    async fn batch_submit(&self, batch: u64) -> Result<Vec<Vec<f32>>, GpuError> {
+        GpuManager::batch_submit(self, batch).await
+    }
+
+    fn release_buffers(&self) {
+        GpuManager::release_buffers(self)
+    }
+}
+
+/// the backend picked at startup, gpu when an adapter exists, cpu otherwise
+#[derive(Clone)]
+pub enum Backend {
+    Gpu(GpuManager),
+    Cpu(CpuBackend),
+}
+
+macro_rules! on_backend {
+    ($self_:expr, $backend:ident => $call:expr) => {
+        match $self_ {
+            Backend::Gpu($backend) => $call,
+            Backend::Cpu($backend) => $call,
+        }
+    };
+}
+
+impl Backend {
+    /// the gpu manager for things only a gpu can do (non f32 dtypes, runtime wgsl kernels)
+    pub fn gpu(&self) -> Result<&GpuManager, GpuError> {
+        match self {
+            Backend::Gpu(gpu) => Ok(gpu),
+            Backend::Cpu(_) => Err(GpuError::NoAdapter),
+        }
+    }
+}
+
+impl ComputeBackend for Backend {
+    fn name(&self) -> &'static str {
+        on_backend!(self, backend => ComputeBackend::name(backend))
+    }
+
+    async fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::add(backend, a, b).await)
+    }
+
+    async fn matrix_multiply(&self, a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::matrix_multiply(backend, a, b).await)
+    }
+
+    async fn vec_matrix_multiply(&self, a: Vec<f32>, b: Vec<Vec<f32>>) -> Result<Vec<f32>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::vec_matrix_multiply(backend, a, b).await)
+    }
+
+    async fn gemm(&self, spec: &GemmSpec, a: &[f32], b: &[f32], c: Option<&[f32]>, bias: Option<&[f32]>) -> Result<Vec<f32>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::gemm(backend, spec, a, b, c, bias).await)
+    }
+
+    fn tensor_upload(&self, data: &[f32], shape: Vec<u32>) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_upload(backend, data, shape))
+    }
+
+    async fn tensor_download(&self, id: u64) -> Result<Vec<f32>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_download(backend, id).await)
+    }
+
+    fn tensor_shape(&self, id: u64) -> Result<Vec<u32>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_shape(backend, id))
+    }
+
+    fn tensor_retain(&self, id: u64) -> Result<(), GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_retain(backend, id))
+    }
+
+    fn tensor_free(&self, id: u64) -> Result<(), GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_free(backend, id))
+    }
+
+    async fn tensor_add(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_add(backend, a, b).await)
+    }
+
+    async fn tensor_elementwise(&self, op: ElementwiseOp, inputs: &[u64], alpha: f32, beta: f32) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_elementwise(backend, op, inputs, alpha, beta).await)
+    }
+
+    async fn tensor_matmul(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_matmul(backend, a, b).await)
+    }
+
+    async fn tensor_vec_matmul(&self, v: u64, m: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_vec_matmul(backend, v, m).await)
+    }
+
+    async fn tensor_gemm(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_gemm(backend, spec, a, b, c, bias).await)
+    }
+
+    async fn tensor_softmax(&self, id: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_softmax(backend, id).await)
+    }
+
+    async fn tensor_layer_norm(&self, id: u64, gamma: u64, beta: u64, eps: f32) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_layer_norm(backend, id, gamma, beta, eps).await)
+    }
+
+    async fn tensor_rms_norm(&self, id: u64, gamma: u64, eps: f32) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_rms_norm(backend, id, gamma, eps).await)
+    }
+
+    async fn tensor_gelu(&self, id: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_gelu(backend, id).await)
+    }
+
+    async fn tensor_silu(&self, id: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_silu(backend, id).await)
+    }
+
+    async fn tensor_rope(&self, id: u64, offset: u32, base: f32) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_rope(backend, id, offset, base).await)
+    }
+
+    async fn tensor_attention(&self, q: u64, k: u64, v: u64, causal: bool) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_attention(backend, q, k, v, causal).await)
+    }
+
+    async fn tensor_conv2d(&self, x: u64, weight: u64, bias: Option<u64>, spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_conv2d(backend, x, weight, bias, spec).await)
+    }
+
+    async fn tensor_pool2d(&self, x: u64, mode: PoolMode, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_pool2d(backend, x, mode, kernel, spec).await)
+    }
+
+    async fn tensor_im2col(&self, x: u64, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_im2col(backend, x, kernel, spec).await)
+    }
+
+    async fn tensor_sum(&self, id: u64) -> Result<f32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_sum(backend, id).await)
+    }
+
+    async fn tensor_mean(&self, id: u64) -> Result<f32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_mean(backend, id).await)
+    }
+
+    async fn tensor_min(&self, id: u64) -> Result<f32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_min(backend, id).await)
+    }
+
+    async fn tensor_max(&self, id: u64) -> Result<f32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_max(backend, id).await)
+    }
+
+    async fn tensor_dot(&self, a: u64, b: u64) -> Result<f32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_dot(backend, a, b).await)
+    }
+
+    async fn tensor_argmax(&self, id: u64) -> Result<u32, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_argmax(backend, id).await)
+    }
+
+    async fn tensor_scan(&self, id: u64, exclusive: bool) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::tensor_scan(backend, id, exclusive).await)
+    }
+
+    fn batch_new(&self) -> u64 {
+        on_backend!(self, backend => ComputeBackend::batch_new(backend))
+    }
+
+    fn batch_add(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::batch_add(backend, batch, a, b))
+    }
+
+    fn batch_matmul(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::batch_matmul(backend, batch, a, b))
+    }
+
+    fn batch_vec_matmul(&self, batch: u64, v: u64, m: u64) -> Result<u64, GpuError> {
+        on_backend!(self, backend => ComputeBackend::batch_vec_matmul(backend, batch, v, m))
+    }
+
+    fn batch_read(&self, batch: u64, tensor: u64) -> Result<(), GpuError> {
+        on_backend!(self, backend => ComputeBackend::batch_read(backend, batch, tensor))
+    }
+
+    async fn batch_submit(&self, batch: u64) -> Result<Vec<Vec<f32>>, GpuError> {
+        on_backend!(self, backend => ComputeBackend::batch_submit(backend, batch).await)
+    }
+
+    fn release_buffers(&self) {
+        on_backend!(self, backend => ComputeBackend::release_buffers(backend))
+    }
+}
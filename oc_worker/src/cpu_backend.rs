@@ -0,0 +1,910 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::compute_backend::ComputeBackend;
+use crate::gpu_conv::{conv2d_params, im2col_params, pool2d_params, tap, Conv2dSpec, ConvParams, PoolMode};
+use crate::gpu_elementwise::ElementwiseOp;
+use crate::gpu_error::GpuError;
+use crate::gpu_gemm::GemmSpec;
+use crate::gpu_nn::{attention_dims, causal_limit, check_weight, rope_dims, rows_cols};
+use crate::gpu_reduce::{check_not_empty, tree_sum};
+use crate::gpu_tensor::{element_count, matrix_dims, HandleSpace};
+
+struct CpuTensor {
+    data: Arc<Vec<f32>>,
+    shape: Vec<u32>,
+    refcount: u32,
+}
+
+/// handle -> tensor, same rules as the gpu `TensorStore`
+struct CpuTensorStore {
+    ids: HandleSpace,
+    tensors: HashMap<u64, CpuTensor>,
+}
+
+impl CpuTensorStore {
+    fn get_mut(&mut self, id: u64) -> Result<&mut CpuTensor, GpuError> {
+        self.tensors.get_mut(&id).ok_or_else(|| self.ids.missing("tensor", id))
+    }
+}
+
+/// batch handle -> tensors to read on submit, the ops themselves run as they are recorded
+struct CpuBatchStore {
+    ids: HandleSpace,
+    reads: HashMap<u64, Vec<u64>>,
+}
+
+impl CpuBatchStore {
+    fn get_mut(&mut self, id: u64) -> Result<&mut Vec<u64>, GpuError> {
+        self.reads.get_mut(&id).ok_or_else(|| self.ids.missing("batch", id))
+    }
+}
+
+/// runs every kernel on the cpu, used when there is no adapter
+#[derive(Clone)]
+pub struct CpuBackend {
+    threads: usize,
+    tensors: Arc<Mutex<CpuTensorStore>>,
+    batches: Arc<Mutex<CpuBatchStore>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_threads() -> usize {
+    // single threaded on purpose: the worker is built without the atomics and shared memory
+    // target features, so it has no std threads, and the wasm_thread_manager "threads" are
+    // spawn_local tasks on this same js thread, spreading rows over them would only add copies
+    1
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn flatten_matrix(m: Vec<Vec<f32>>) -> Result<(Vec<f32>, u32, u32), GpuError> {
+    let long = m.len() as u32;
+    let width = m.first().map_or(0, |r| r.len() as u32);
+    if m.iter().any(|r| r.len() as u32 != width) {
+        return Err(GpuError::ShapeMismatch("matrix rows must all have the same length".into()));
+    }
+    Ok((m.into_iter().flatten().collect(), long, width))
+}
+
+impl CpuBackend {
+    pub fn new() -> Self {
+        Self {
+            threads: default_threads(),
+            tensors: Arc::new(Mutex::new(CpuTensorStore { ids: HandleSpace::new(), tensors: HashMap::new() })),
+            batches: Arc::new(Mutex::new(CpuBatchStore { ids: HandleSpace::new(), reads: HashMap::new() })),
+        }
+    }
+
+    /// fill `out` row by row, rows are spread over the threads in contiguous blocks,
+    /// always on the calling thread on wasm32
+    fn for_rows<F>(&self, out: &mut [f32], width: usize, f: F)
+    where
+        F: Fn(usize, &mut [f32]) + Sync,
+    {
+        if width == 0 || out.is_empty() {
+            return;
+        }
+        let rows = out.len() / width;
+        let threads = self.threads.min(rows).max(1);
+
+        if threads == 1 {
+            for (i, row) in out.chunks_mut(width).enumerate() {
+                f(i, row);
+            }
+            return;
+        }
+
+        let rows_per_thread = rows.div_ceil(threads);
+        std::thread::scope(|scope| {
+            for (block, chunk) in out.chunks_mut(rows_per_thread * width).enumerate() {
+                let f = &f;
+                scope.spawn(move || {
+                    for (i, row) in chunk.chunks_mut(width).enumerate() {
+                        f(block * rows_per_thread + i, row);
+                    }
+                });
+            }
+        });
+    }
+
+    fn add_slices(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, GpuError> {
+        if a.len() != b.len() {
+            return Err(GpuError::ShapeMismatch(format!("input lengths {} vs {}", a.len(), b.len())));
+        }
+        let mut out = vec![0.0; a.len()];
+        self.for_rows(&mut out, 1, |i, o| o[0] = a[i] + b[i]);
+        Ok(out)
+    }
+
+    /// row major `a` (long x inner) times `b` (inner x width), sums over `inner` in increasing order
+    /// like the gpu kernel, results may still differ from the device in the last bits where it fuses
+    /// multiply and add
+    fn matmul_slices(&self, a: &[f32], b: &[f32], long: u32, inner: u32, width: u32) -> Vec<f32> {
+        let (inner, width) = (inner as usize, width as usize);
+        let mut out = vec![0.0; long as usize * width];
+        self.for_rows(&mut out, width, |i, row| {
+            let a_row = &a[i * inner..(i + 1) * inner];
+            for (k, a_ik) in a_row.iter().enumerate() {
+                let b_row = &b[k * width..(k + 1) * width];
+                for (o, b_kj) in row.iter_mut().zip(b_row) {
+                    *o += a_ik * b_kj;
+                }
+            }
+        });
+        out
+    }
+
+    fn vec_matmul_slices(&self, v: &[f32], m: &[f32], long: u32, width: u32) -> Vec<f32> {
+        let (long, width) = (long as usize, width as usize);
+        let mut out = vec![0.0; width];
+        self.for_rows(&mut out, 1, |j, o| {
+            o[0] = (0..long).fold(0.0, |sum, k| sum + v[k] * m[k * width + j]);
+        });
+        out
+    }
+
+    /// gemm of a resolved and checked spec into `out`, which starts as a copy of c or zeros
+    fn gemm_slices(&self, spec: &GemmSpec, a: &[f32], b: &[f32], c: Option<&[f32]>, bias: Option<&[f32]>, out: &mut [f32]) {
+        let (m, n, k) = (spec.m as usize, spec.n as usize, spec.k as usize);
+        let (lda, ldb, ldc) = (spec.lda as usize, spec.ldb as usize, spec.ldc as usize);
+
+        for batch in 0..spec.batch as usize {
+            let a_base = batch * spec.stride_a as usize;
+            let b_base = batch * spec.stride_b as usize;
+            let c_base = batch * spec.stride_c as usize;
+            let a_at = |i: usize, p: usize| if spec.trans_a { a[a_base + p * lda + i] } else { a[a_base + i * lda + p] };
+            let b_at = |p: usize, j: usize| if spec.trans_b { b[b_base + j * ldb + p] } else { b[b_base + p * ldb + j] };
+
+            let mut block = vec![0.0; m * n];
+            self.for_rows(&mut block, n, |i, row| {
+                for (j, o) in row.iter_mut().enumerate() {
+                    let sum = (0..k).fold(0.0, |sum, p| sum + a_at(i, p) * b_at(p, j));
+                    let mut value = spec.alpha * sum;
+                    if let Some(c) = c {
+                        value += spec.beta * c[c_base + i * ldc + j];
+                    }
+                    if let Some(bias) = bias {
+                        value += bias[j];
+                    }
+                    *o = value;
+                }
+            });
+
+            for (i, row) in block.chunks(n.max(1)).enumerate() {
+                out[c_base + i * ldc..c_base + i * ldc + n].copy_from_slice(row);
+            }
+        }
+    }
+
+    // reference versions of the nn kernels, they visit values in the same order as the wgsl
+
+    fn softmax_rows(&self, x: &[f32], cols: usize) -> Vec<f32> {
+        let mut out = vec![0.0; x.len()];
+        self.for_rows(&mut out, cols, |i, row| {
+            let x_row = &x[i * cols..(i + 1) * cols];
+            let m = x_row[1..].iter().fold(x_row[0], |m, v| m.max(*v));
+            let mut sum = 0.0;
+            for (o, v) in row.iter_mut().zip(x_row) {
+                *o = (v - m).exp();
+                sum += *o;
+            }
+            for o in row.iter_mut() {
+                *o /= sum;
+            }
+        });
+        out
+    }
+
+    fn layer_norm_rows(&self, x: &[f32], gamma: &[f32], beta: &[f32], cols: usize, eps: f32) -> Vec<f32> {
+        let mut out = vec![0.0; x.len()];
+        self.for_rows(&mut out, cols, |i, row| {
+            let x_row = &x[i * cols..(i + 1) * cols];
+            let n = cols as f32;
+            let mean = x_row.iter().fold(0.0, |sum, v| sum + v) / n;
+            let variance = x_row.iter().fold(0.0, |sum, v| sum + (v - mean) * (v - mean));
+            let inv = 1.0 / (variance / n + eps).sqrt();
+            for (j, o) in row.iter_mut().enumerate() {
+                *o = (x_row[j] - mean) * inv * gamma[j] + beta[j];
+            }
+        });
+        out
+    }
+
+    fn rms_norm_rows(&self, x: &[f32], gamma: &[f32], cols: usize, eps: f32) -> Vec<f32> {
+        let mut out = vec![0.0; x.len()];
+        self.for_rows(&mut out, cols, |i, row| {
+            let x_row = &x[i * cols..(i + 1) * cols];
+            let sum_sq = x_row.iter().fold(0.0, |sum, v| sum + v * v);
+            let inv = 1.0 / (sum_sq / cols as f32 + eps).sqrt();
+            for (j, o) in row.iter_mut().enumerate() {
+                *o = x_row[j] * inv * gamma[j];
+            }
+        });
+        out
+    }
+
+    fn map_values(&self, x: &[f32], f: impl Fn(f32) -> f32 + Sync) -> Vec<f32> {
+        let mut out = vec![0.0; x.len()];
+        self.for_rows(&mut out, 1, |i, o| o[0] = f(x[i]));
+        out
+    }
+
+    fn rope_rows(&self, x: &[f32], dim: usize, offset: u32, base: f32) -> Vec<f32> {
+        let mut out = vec![0.0; x.len()];
+        self.for_rows(&mut out, dim, |row, out_row| {
+            let x_row = &x[row * dim..(row + 1) * dim];
+            let pos = (row as u32 + offset) as f32;
+            for i in 0..dim / 2 {
+                let theta = pos * base.powf(-2.0 * i as f32 / dim as f32);
+                let (s, c) = theta.sin_cos();
+                let (x0, x1) = (x_row[2 * i], x_row[2 * i + 1]);
+                out_row[2 * i] = x0 * c - x1 * s;
+                out_row[2 * i + 1] = x0 * s + x1 * c;
+            }
+        });
+        out
+    }
+
+    /// one query row at a time with the same online softmax as the gpu kernel
+    fn attention_rows(&self, q: &[f32], k: &[f32], v: &[f32], dims: (u32, u32, u32, u32), causal: bool) -> Vec<f32> {
+        let (seq_q, d, seq_k, dv) = dims;
+        let (d, dv) = (d as usize, dv as usize);
+        let scale = 1.0 / (d as f32).sqrt();
+
+        let mut out = vec![0.0; seq_q as usize * dv];
+        self.for_rows(&mut out, dv, |row, out_row| {
+            let last = (if causal { causal_limit(row as u32, seq_q, seq_k) } else { seq_k }) as usize;
+            let q_row = &q[row * d..(row + 1) * d];
+
+            let mut m = -3.4e38f32;
+            let mut l = 0.0;
+            for j in 0..last {
+                let k_row = &k[j * d..(j + 1) * d];
+                let score = q_row.iter().zip(k_row).fold(0.0, |sum, (a, b)| sum + a * b) * scale;
+
+                let m_new = m.max(score);
+                let correction = (m - m_new).exp();
+                let p = (score - m_new).exp();
+                l = l * correction + p;
+                for (o, v_j) in out_row.iter_mut().zip(&v[j * dv..(j + 1) * dv]) {
+                    *o = *o * correction + p * v_j;
+                }
+                m = m_new;
+            }
+
+            if l > 0.0 {
+                for o in out_row.iter_mut() {
+                    *o /= l;
+                }
+            }
+        });
+        out
+    }
+
+    // reference versions of the conv kernels, one row of `ow` outputs at a time
+
+    fn conv2d_rows(&self, p: &ConvParams, x: &[f32], weight: &[f32], bias: Option<&[f32]>) -> Vec<f32> {
+        let (c, h, w, oc, oh, ow) = (p.c as usize, p.h as usize, p.w as usize, p.oc as usize, p.oh as usize, p.ow as usize);
+        let (kh, kw) = (p.kh as usize, p.kw as usize);
+        let cpg = c / p.groups as usize;
+
+        let mut out = vec![0.0; p.n as usize * oc * oh * ow];
+        self.for_rows(&mut out, ow, |row, out_row| {
+            let oy = (row % oh) as u32;
+            let o = (row / oh) % oc;
+            let b = row / (oh * oc);
+            let g = o / (oc / p.groups as usize);
+
+            for (ox, value) in out_row.iter_mut().enumerate() {
+                let mut sum = 0.0;
+                for ci in 0..cpg {
+                    let ic = g * cpg + ci;
+                    for ky in 0..kh {
+                        let Some(iy) = tap(oy, ky as u32, p.sh, p.dh, p.ph, p.h) else {
+                            continue;
+                        };
+                        for kx in 0..kw {
+                            let Some(ix) = tap(ox as u32, kx as u32, p.sw, p.dw, p.pw, p.w) else {
+                                continue;
+                            };
+                            sum += x[((b * c + ic) * h + iy) * w + ix] * weight[((o * cpg + ci) * kh + ky) * kw + kx];
+                        }
+                    }
+                }
+                if let Some(bias) = bias {
+                    sum += bias[o];
+                }
+                *value = sum;
+            }
+        });
+        out
+    }
+
+    /// padding never counts towards an average
+    fn pool2d_rows(&self, p: &ConvParams, x: &[f32], mode: PoolMode) -> Vec<f32> {
+        let (h, w, oh, ow) = (p.h as usize, p.w as usize, p.oh as usize, p.ow as usize);
+
+        let mut out = vec![0.0; (p.n * p.c) as usize * oh * ow];
+        self.for_rows(&mut out, ow, |row, out_row| {
+            let oy = (row % oh) as u32;
+            let plane = row / oh;
+
+            for (ox, value) in out_row.iter_mut().enumerate() {
+                let mut acc = if mode == PoolMode::Avg { 0.0 } else { -3.4028235e38f32 };
+                let mut count = 0u32;
+                for ky in 0..p.kh {
+                    let Some(iy) = tap(oy, ky, p.sh, p.dh, p.ph, p.h) else {
+                        continue;
+                    };
+                    for kx in 0..p.kw {
+                        let Some(ix) = tap(ox as u32, kx, p.sw, p.dw, p.pw, p.w) else {
+                            continue;
+                        };
+                        let v = x[(plane * h + iy) * w + ix];
+                        acc = if mode == PoolMode::Avg { acc + v } else { acc.max(v) };
+                        count += 1;
+                    }
+                }
+                if mode == PoolMode::Avg {
+                    acc /= count.max(1) as f32;
+                }
+                *value = acc;
+            }
+        });
+        out
+    }
+
+    /// zero where the tap falls into the padding
+    fn im2col_rows(&self, p: &ConvParams, x: &[f32]) -> Vec<f32> {
+        let (c, h, w, ow) = (p.c as usize, p.h as usize, p.w as usize, p.ow as usize);
+        let (kh, kw) = (p.kh as usize, p.kw as usize);
+        let cols = (p.oh * p.ow) as usize;
+        let rows = c * kh * kw;
+
+        let mut out = vec![0.0; p.n as usize * rows * cols];
+        self.for_rows(&mut out, cols, |out_row_index, out_row| {
+            let row = out_row_index % rows;
+            let b = out_row_index / rows;
+            let (ic, ky, kx) = (row / (kh * kw), (row / kw) % kh, row % kw);
+
+            for (col, value) in out_row.iter_mut().enumerate() {
+                let iy = tap((col / ow) as u32, ky as u32, p.sh, p.dh, p.ph, p.h);
+                let ix = tap((col % ow) as u32, kx as u32, p.sw, p.dw, p.pw, p.w);
+                *value = match (iy, ix) {
+                    (Some(iy), Some(ix)) => x[((b * c + ic) * h + iy) * w + ix],
+                    _ => 0.0,
+                };
+            }
+        });
+        out
+    }
+
+    fn insert(&self, data: Vec<f32>, shape: Vec<u32>) -> u64 {
+        let mut store = self.tensors.lock().unwrap();
+        let id = store.ids.next_id();
+        store.tensors.insert(id, CpuTensor { data: Arc::new(data), shape, refcount: 1 });
+        id
+    }
+
+    fn tensor(&self, id: u64) -> Result<(Arc<Vec<f32>>, Vec<u32>), GpuError> {
+        let mut store = self.tensors.lock().unwrap();
+        let tensor = store.get_mut(id)?;
+        Ok((tensor.data.clone(), tensor.shape.clone()))
+    }
+
+    fn check_batch(&self, batch: u64) -> Result<(), GpuError> {
+        self.batches.lock().unwrap().get_mut(batch)?;
+        Ok(())
+    }
+
+    fn add_tensors(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        let (data_a, shape_a) = self.tensor(a)?;
+        let (data_b, shape_b) = self.tensor(b)?;
+        if shape_a != shape_b {
+            return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", shape_a, shape_b)));
+        }
+
+        let out = self.add_slices(&data_a, &data_b)?;
+        Ok(self.insert(out, shape_a))
+    }
+
+    fn matmul_tensors(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        let (data_a, shape_a) = self.tensor(a)?;
+        let (data_b, shape_b) = self.tensor(b)?;
+        let (a_long, a_width) = matrix_dims(&shape_a)?;
+        let (b_long, b_width) = matrix_dims(&shape_b)?;
+        if a_width != b_long {
+            return Err(GpuError::ShapeMismatch("Matrix A's width must equal Matrix B's height.".into()));
+        }
+
+        let out = self.matmul_slices(&data_a, &data_b, a_long, a_width, b_width);
+        Ok(self.insert(out, vec![a_long, b_width]))
+    }
+
+    fn vec_matmul_tensors(&self, v: u64, m: u64) -> Result<u64, GpuError> {
+        let (data_v, shape_v) = self.tensor(v)?;
+        let (data_m, shape_m) = self.tensor(m)?;
+        let (m_long, m_width) = matrix_dims(&shape_m)?;
+        if element_count(&shape_v)? != m_long {
+            return Err(GpuError::ShapeMismatch("Vector A's width must equal Matrix B's height.".into()));
+        }
+
+        let out = self.vec_matmul_slices(&data_v, &data_m, m_long, m_width);
+        Ok(self.insert(out, vec![m_width]))
+    }
+}
+
+impl ComputeBackend for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    async fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, GpuError> {
+        self.add_slices(a, b)
+    }
+
+    async fn matrix_multiply(&self, a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, GpuError> {
+        let (a_data, a_long, a_width) = flatten_matrix(a)?;
+        let (b_data, b_long, b_width) = flatten_matrix(b)?;
+        if a_width != b_long {
+            return Err(GpuError::ShapeMismatch("Matrix A's width must equal Matrix B's height.".into()));
+        }
+
+        let out = self.matmul_slices(&a_data, &b_data, a_long, a_width, b_width);
+        Ok(out.chunks(b_width.max(1) as usize).map(|row| row.to_vec()).collect())
+    }
+
+    async fn vec_matrix_multiply(&self, a: Vec<f32>, b: Vec<Vec<f32>>) -> Result<Vec<f32>, GpuError> {
+        let (b_data, b_long, b_width) = flatten_matrix(b)?;
+        if a.len() as u32 != b_long {
+            return Err(GpuError::ShapeMismatch("Vector A's width must equal Matrix B's height.".into()));
+        }
+
+        Ok(self.vec_matmul_slices(&a, &b_data, b_long, b_width))
+    }
+
+    async fn gemm(&self, spec: &GemmSpec, a: &[f32], b: &[f32], c: Option<&[f32]>, bias: Option<&[f32]>) -> Result<Vec<f32>, GpuError> {
+        let spec = spec.resolved();
+        let len = spec.check(a.len() as u64, b.len() as u64, c.map(|c| c.len() as u64), bias.map(|b| b.len() as u64))?;
+
+        let mut out = c.map_or_else(|| vec![0.0; len as usize], |c| c.to_vec());
+        self.gemm_slices(&spec, a, b, c, bias, &mut out);
+        Ok(out)
+    }
+
+    fn tensor_upload(&self, data: &[f32], shape: Vec<u32>) -> Result<u64, GpuError> {
+        let len = element_count(&shape)? as u64;
+        if len != data.len() as u64 {
+            return Err(GpuError::ShapeMismatch(format!("shape {:?} does not match {} values", shape, data.len())));
+        }
+        Ok(self.insert(data.to_vec(), shape))
+    }
+
+    async fn tensor_download(&self, id: u64) -> Result<Vec<f32>, GpuError> {
+        Ok(self.tensor(id)?.0.to_vec())
+    }
+
+    fn tensor_shape(&self, id: u64) -> Result<Vec<u32>, GpuError> {
+        Ok(self.tensor(id)?.1)
+    }
+
+    fn tensor_retain(&self, id: u64) -> Result<(), GpuError> {
+        self.tensors.lock().unwrap().get_mut(id)?.refcount += 1;
+        Ok(())
+    }
+
+    fn tensor_free(&self, id: u64) -> Result<(), GpuError> {
+        let mut store = self.tensors.lock().unwrap();
+        let tensor = store.get_mut(id)?;
+        tensor.refcount -= 1;
+        if tensor.refcount == 0 {
+            store.tensors.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn tensor_add(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        self.add_tensors(a, b)
+    }
+
+    async fn tensor_elementwise(&self, op: ElementwiseOp, inputs: &[u64], alpha: f32, beta: f32) -> Result<u64, GpuError> {
+        op.check(inputs.len(), alpha, beta)?;
+        let operands = inputs.iter().map(|id| self.tensor(*id)).collect::<Result<Vec<_>, _>>()?;
+        let shape = operands[0].1.clone();
+        if let Some((_, other)) = operands.iter().find(|(_, other)| *other != shape) {
+            return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", shape, other)));
+        }
+
+        let mut out = vec![0.0; operands[0].0.len()];
+        self.for_rows(&mut out, 1, |i, o| {
+            let mut x = [0.0; 3];
+            for (slot, (data, _)) in x.iter_mut().zip(&operands) {
+                *slot = data[i];
+            }
+            o[0] = op.apply(&x, alpha, beta);
+        });
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_matmul(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        self.matmul_tensors(a, b)
+    }
+
+    async fn tensor_vec_matmul(&self, v: u64, m: u64) -> Result<u64, GpuError> {
+        self.vec_matmul_tensors(v, m)
+    }
+
+    async fn tensor_gemm(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError> {
+        let (data_a, _) = self.tensor(a)?;
+        let (data_b, _) = self.tensor(b)?;
+        let c = c.map(|id| self.tensor(id)).transpose()?;
+        let bias = bias.map(|id| self.tensor(id)).transpose()?;
+
+        let out = ComputeBackend::gemm(
+            self,
+            spec,
+            &data_a,
+            &data_b,
+            c.as_ref().map(|(data, _)| data.as_slice()),
+            bias.as_ref().map(|(data, _)| data.as_slice()),
+        )
+        .await?;
+        let shape = match c {
+            Some((_, shape)) => shape,
+            None => spec.resolved().out_shape(out.len() as u64),
+        };
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_softmax(&self, id: u64) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let (_, cols) = rows_cols(&shape)?;
+
+        let out = self.softmax_rows(&data, cols as usize);
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_layer_norm(&self, id: u64, gamma: u64, beta: u64, eps: f32) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let (gamma_data, gamma_shape) = self.tensor(gamma)?;
+        let (beta_data, beta_shape) = self.tensor(beta)?;
+        let (_, cols) = rows_cols(&shape)?;
+        check_weight("gamma", &gamma_shape, cols)?;
+        check_weight("beta", &beta_shape, cols)?;
+
+        let out = self.layer_norm_rows(&data, &gamma_data, &beta_data, cols as usize, eps);
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_rms_norm(&self, id: u64, gamma: u64, eps: f32) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let (gamma_data, gamma_shape) = self.tensor(gamma)?;
+        let (_, cols) = rows_cols(&shape)?;
+        check_weight("gamma", &gamma_shape, cols)?;
+
+        let out = self.rms_norm_rows(&data, &gamma_data, cols as usize, eps);
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_gelu(&self, id: u64) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let out = self.map_values(&data, |v| 0.5 * v * (1.0 + (0.797_884_6 * (v + 0.044715 * v * v * v)).tanh()));
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_silu(&self, id: u64) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let out = self.map_values(&data, |v| v / (1.0 + (-v).exp()));
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_rope(&self, id: u64, offset: u32, base: f32) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let (_, dim) = rope_dims(&shape)?;
+
+        let out = self.rope_rows(&data, dim as usize, offset, base);
+        Ok(self.insert(out, shape))
+    }
+
+    async fn tensor_attention(&self, q: u64, k: u64, v: u64, causal: bool) -> Result<u64, GpuError> {
+        let (q_data, q_shape) = self.tensor(q)?;
+        let (k_data, k_shape) = self.tensor(k)?;
+        let (v_data, v_shape) = self.tensor(v)?;
+        let dims = attention_dims(&q_shape, &k_shape, &v_shape)?;
+
+        let out = self.attention_rows(&q_data, &k_data, &v_data, dims, causal);
+        Ok(self.insert(out, vec![dims.0, dims.3]))
+    }
+
+    async fn tensor_conv2d(&self, x: u64, weight: u64, bias: Option<u64>, spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (data_x, shape_x) = self.tensor(x)?;
+        let (data_w, shape_w) = self.tensor(weight)?;
+        let bias = bias.map(|id| self.tensor(id)).transpose()?;
+        let (params, out_shape) = conv2d_params(spec, &shape_x, &shape_w, bias.as_ref().map(|(_, shape)| shape.as_slice()))?;
+
+        let out = self.conv2d_rows(&params, &data_x, &data_w, bias.as_ref().map(|(data, _)| data.as_slice()));
+        Ok(self.insert(out, out_shape))
+    }
+
+    async fn tensor_pool2d(&self, x: u64, mode: PoolMode, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(x)?;
+        let (params, out_shape) = pool2d_params(spec, &shape, mode, kernel)?;
+
+        let out = self.pool2d_rows(&params, &data, mode);
+        Ok(self.insert(out, out_shape))
+    }
+
+    async fn tensor_im2col(&self, x: u64, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(x)?;
+        let (params, out_shape) = im2col_params(spec, &shape, kernel)?;
+
+        let out = self.im2col_rows(&params, &data);
+        Ok(self.insert(out, out_shape))
+    }
+
+    async fn tensor_sum(&self, id: u64) -> Result<f32, GpuError> {
+        Ok(tree_sum(self.tensor(id)?.0.to_vec()))
+    }
+
+    async fn tensor_mean(&self, id: u64) -> Result<f32, GpuError> {
+        let (data, _) = self.tensor(id)?;
+        check_not_empty("mean", data.len())?;
+        Ok(tree_sum(data.to_vec()) / data.len() as f32)
+    }
+
+    async fn tensor_min(&self, id: u64) -> Result<f32, GpuError> {
+        let (data, _) = self.tensor(id)?;
+        check_not_empty("min", data.len())?;
+        Ok(data.iter().fold(f32::INFINITY, |m, v| m.min(*v)))
+    }
+
+    async fn tensor_max(&self, id: u64) -> Result<f32, GpuError> {
+        let (data, _) = self.tensor(id)?;
+        check_not_empty("max", data.len())?;
+        Ok(data.iter().fold(f32::NEG_INFINITY, |m, v| m.max(*v)))
+    }
+
+    async fn tensor_dot(&self, a: u64, b: u64) -> Result<f32, GpuError> {
+        let (data_a, shape_a) = self.tensor(a)?;
+        let (data_b, shape_b) = self.tensor(b)?;
+        if data_a.len() != data_b.len() {
+            return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", shape_a, shape_b)));
+        }
+        Ok(tree_sum(data_a.iter().zip(data_b.iter()).map(|(x, y)| x * y).collect()))
+    }
+
+    async fn tensor_argmax(&self, id: u64) -> Result<u32, GpuError> {
+        let (data, _) = self.tensor(id)?;
+        check_not_empty("argmax", data.len())?;
+
+        // strictly greater keeps the first of equal values, comparisons with NaN are false
+        let mut best: Option<(u32, f32)> = None;
+        for (i, v) in data.iter().enumerate() {
+            let better = match best {
+                Some((_, b)) => *v > b,
+                None => !v.is_nan(),
+            };
+            if better {
+                best = Some((i as u32, *v));
+            }
+        }
+        best.map(|(i, _)| i)
+            .ok_or_else(|| GpuError::InvalidArgument("argmax of a tensor holding only NaN".into()))
+    }
+
+    /// sequential, may differ from the gpu block scan in the last bits
+    async fn tensor_scan(&self, id: u64, exclusive: bool) -> Result<u64, GpuError> {
+        let (data, shape) = self.tensor(id)?;
+        let mut sum = 0.0;
+        let out = data
+            .iter()
+            .map(|v| {
+                let before = sum;
+                sum += v;
+                if exclusive { before } else { sum }
+            })
+            .collect();
+        Ok(self.insert(out, shape))
+    }
+
+    fn batch_new(&self) -> u64 {
+        let mut store = self.batches.lock().unwrap();
+        let id = store.ids.next_id();
+        store.reads.insert(id, Vec::new());
+        id
+    }
+
+    fn batch_add(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        self.check_batch(batch)?;
+        self.add_tensors(a, b)
+    }
+
+    fn batch_matmul(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        self.check_batch(batch)?;
+        self.matmul_tensors(a, b)
+    }
+
+    fn batch_vec_matmul(&self, batch: u64, v: u64, m: u64) -> Result<u64, GpuError> {
+        self.check_batch(batch)?;
+        self.vec_matmul_tensors(v, m)
+    }
+
+    fn batch_read(&self, batch: u64, tensor: u64) -> Result<(), GpuError> {
+        self.tensor(tensor)?;
+        self.batches.lock().unwrap().get_mut(batch)?.push(tensor);
+        Ok(())
+    }
+
+    async fn batch_submit(&self, batch: u64) -> Result<Vec<Vec<f32>>, GpuError> {
+        let reads = {
+            let mut store = self.batches.lock().unwrap();
+            store.reads.remove(&batch).ok_or_else(|| store.ids.missing("batch", batch))?
+        };
+        reads.into_iter().map(|id| Ok(self.tensor(id)?.0.to_vec())).collect()
+    }
+
+    fn release_buffers(&self) {
+        self.tensors.lock().unwrap().tensors.clear();
+        self.batches.lock().unwrap().reads.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() <= 1e-5 * (1.0 + y.abs()))
+    }
+
+    fn argmax(backend: &CpuBackend, data: &[f32]) -> Result<u32, GpuError> {
+        let id = backend.tensor_upload(data, vec![data.len() as u32]).unwrap();
+        futures::executor::block_on(backend.tensor_argmax(id))
+    }
+
+    #[test]
+    fn softmax_rows_sum_to_one() {
+        let backend = CpuBackend::new();
+        let x = [1.0, 2.0, 3.0, 1000.0, 1000.0, 1000.0];
+        let out = backend.softmax_rows(&x, 3);
+
+        let e = [(-2.0f32).exp(), (-1.0f32).exp(), 1.0];
+        let sum: f32 = e.iter().sum();
+        let expected: Vec<f32> = e.iter().map(|v| v / sum).chain([1.0 / 3.0; 3]).collect();
+        assert!(close(&out, &expected), "{:?}", out);
+    }
+
+    #[test]
+    fn layer_norm_rows_normalize_each_row() {
+        let backend = CpuBackend::new();
+        let x = [1.0, 2.0, 3.0, 4.0, -2.0, 0.0, 2.0, 4.0];
+        let out = backend.layer_norm_rows(&x, &[2.0; 4], &[0.5; 4], 4, 0.0);
+
+        for (row, x_row) in out.chunks(4).zip(x.chunks(4)) {
+            let mean = x_row.iter().sum::<f32>() / 4.0;
+            let std = (x_row.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / 4.0).sqrt();
+            let expected: Vec<f32> = x_row.iter().map(|v| (v - mean) / std * 2.0 + 0.5).collect();
+            assert!(close(row, &expected), "{:?}", row);
+        }
+    }
+
+    #[test]
+    fn rms_norm_rows_scale_by_root_mean_square() {
+        let backend = CpuBackend::new();
+        let out = backend.rms_norm_rows(&[3.0, 4.0, 0.0, 0.0], &[1.0, 2.0], 2, 1e-6);
+
+        let rms = 12.5f32.sqrt();
+        assert!(close(&out[..2], &[3.0 / rms, 8.0 / rms]), "{:?}", out);
+        // eps keeps a zero row finite
+        assert!(out[2..].iter().all(|v| *v == 0.0), "{:?}", out);
+    }
+
+    #[test]
+    fn rope_rows_rotate_pairs_by_position() {
+        let backend = CpuBackend::new();
+        let x = [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0];
+        let out = backend.rope_rows(&x, 4, 2, 10000.0);
+
+        for (row, out_row) in out.chunks(4).enumerate() {
+            let pos = (row + 2) as f32;
+            let (theta0, theta1) = (pos, pos * 10000f32.powf(-0.5));
+            let expected = [theta0.cos(), theta0.sin(), -theta1.sin(), theta1.cos()];
+            assert!(close(out_row, &expected), "{:?}", out_row);
+        }
+        // position zero is the identity
+        assert_eq!(backend.rope_rows(&x[..4], 4, 0, 10000.0), x[..4].to_vec());
+    }
+
+    #[test]
+    fn attention_rows_match_naive_softmax() {
+        let backend = CpuBackend::new();
+        let (seq_q, d, seq_k, dv) = (2usize, 2usize, 3usize, 2usize);
+        let q = [1.0, 0.0, 0.5, -1.0];
+        let k = [1.0, 1.0, -1.0, 0.0, 0.0, 2.0];
+        let v = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+
+        for causal in [false, true] {
+            let out = backend.attention_rows(&q, &k, &v, (seq_q as u32, d as u32, seq_k as u32, dv as u32), causal);
+
+            let mut expected = Vec::new();
+            for i in 0..seq_q {
+                let last = if causal { causal_limit(i as u32, seq_q as u32, seq_k as u32) as usize } else { seq_k };
+                let scores: Vec<f32> = (0..last)
+                    .map(|j| (q[i * d] * k[j * d] + q[i * d + 1] * k[j * d + 1]) / (d as f32).sqrt())
+                    .collect();
+                let m = scores.iter().fold(f32::NEG_INFINITY, |m, s| m.max(*s));
+                let weights: Vec<f32> = scores.iter().map(|s| (s - m).exp()).collect();
+                let total: f32 = weights.iter().sum();
+                for c in 0..dv {
+                    expected.push((0..last).map(|j| weights[j] * v[j * dv + c]).sum::<f32>() / total);
+                }
+            }
+            assert!(close(&out, &expected), "causal {}: {:?} vs {:?}", causal, out, expected);
+        }
+    }
+
+    #[test]
+    fn conv2d_matches_im2col_times_weight() {
+        let backend = CpuBackend::new();
+        let spec = Conv2dSpec { stride: (2, 1), padding: (1, 1), ..Default::default() };
+        let x: Vec<f32> = (0..2 * 5 * 4).map(|i| (i % 9) as f32 - 4.0).collect();
+        let weight: Vec<f32> = (0..3 * 2 * 3 * 3).map(|i| (i % 5) as f32 * 0.5 - 1.0).collect();
+
+        let (params, out_shape) = conv2d_params(&spec, &[1, 2, 5, 4], &[3, 2, 3, 3], None).unwrap();
+        let out = backend.conv2d_rows(&params, &x, &weight, None);
+
+        let (cols_params, cols_shape) = im2col_params(&spec, &[1, 2, 5, 4], (3, 3)).unwrap();
+        let cols = backend.im2col_rows(&cols_params, &x);
+        let expected = backend.matmul_slices(&weight, &cols, 3, cols_shape[1], cols_shape[2]);
+
+        assert_eq!(out_shape, vec![1, 3, 3, 4]);
+        assert!(close(&out, &expected), "{:?} vs {:?}", out, expected);
+    }
+
+    #[test]
+    fn pool2d_skips_padding() {
+        let backend = CpuBackend::new();
+        let spec = Conv2dSpec { stride: (2, 2), padding: (1, 1), ..Default::default() };
+        let x = [1.0, 2.0, 3.0, 4.0];
+
+        let (params, _) = pool2d_params(&spec, &[1, 1, 2, 2], PoolMode::Avg, (2, 2)).unwrap();
+        assert_eq!(backend.pool2d_rows(&params, &x, PoolMode::Avg), vec![1.0, 2.0, 3.0, 4.0]);
+        let (params, _) = pool2d_params(&spec, &[1, 1, 2, 2], PoolMode::Max, (2, 2)).unwrap();
+        assert_eq!(backend.pool2d_rows(&params, &[-1.0, -2.0, -3.0, -4.0], PoolMode::Max), vec![-1.0, -2.0, -3.0, -4.0]);
+    }
+
+    #[test]
+    fn argmax_keeps_the_first_of_ties() {
+        let backend = CpuBackend::new();
+        assert_eq!(argmax(&backend, &[1.0, 3.0, 2.0, 3.0]).unwrap(), 1);
+        assert_eq!(argmax(&backend, &[-1.0, -1.0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn argmax_skips_nan() {
+        let backend = CpuBackend::new();
+        assert_eq!(argmax(&backend, &[f32::NAN, 1.0, f32::NAN, 2.0]).unwrap(), 3);
+        assert!(argmax(&backend, &[f32::NAN, f32::NAN]).is_err());
+    }
+
+    #[test]
+    fn batch_reads_come_back_in_order_and_submit_once() {
+        let backend = CpuBackend::new();
+        let a = backend.tensor_upload(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        let b = backend.tensor_upload(&[1.0, 0.0, 0.0, 1.0], vec![2, 2]).unwrap();
+
+        let batch = backend.batch_new();
+        let sum = backend.batch_add(batch, a, b).unwrap();
+        let product = backend.batch_matmul(batch, sum, b).unwrap();
+        backend.batch_read(batch, product).unwrap();
+        backend.batch_read(batch, sum).unwrap();
+
+        let reads = futures::executor::block_on(backend.batch_submit(batch)).unwrap();
+        assert_eq!(reads, vec![vec![2.0, 2.0, 3.0, 5.0], vec![2.0, 2.0, 3.0, 5.0]]);
+        assert!(futures::executor::block_on(backend.batch_submit(batch)).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Deserialize;
+
+use crate::gpu_error::GpuError;
+
+/// options json passed to `worker_start`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkerOptions {
+    pub gpu: AdapterPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerPreference {
+    /// whatever wgpu picks, the behaviour without options
+    #[default]
+    Default,
+    HighPerformance,
+    LowPower,
+}
+
+/// which adapters `GpuManager::open` takes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdapterPolicy {
+    pub power: PowerPreference,
+    /// comma separated wgpu backend names ("vulkan", "metal", "dx12", "gl", "webgpu"), empty for all
+    pub backends: String,
+    /// accept a software adapter when nothing else matches
+    pub allow_fallback: bool,
+    /// case insensitive part of the adapter name, native only
+    pub name: Option<String>,
+    /// open one device per matching adapter and spread runs over them, native only,
+    /// resident tensors belong to the device of the run that made them
+    pub multi_adapter: bool,
+    /// where native devices keep their pipeline caches, the user cache dir when unset
+    pub pipeline_cache_dir: Option<String>,
+    /// buffer pool cap per device in MiB, derived from the device limits when unset
+    pub pool_cap_mb: Option<u64>,
+}
+
+impl PowerPreference {
+    #[cfg(target_arch = "wasm32")]
+    fn wgpu(self) -> wgpu::PowerPreference {
+        match self {
+            PowerPreference::Default => wgpu::PowerPreference::default(),
+            PowerPreference::HighPerformance => wgpu::PowerPreference::HighPerformance,
+            PowerPreference::LowPower => wgpu::PowerPreference::LowPower,
+        }
+    }
+}
+
+impl AdapterPolicy {
+    pub fn instance(&self) -> wgpu::Instance {
+        if self.backends.trim().is_empty() {
+            return wgpu::Instance::default();
+        }
+        wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::from_comma_list(&self.backends),
+            ..Default::default()
+        })
+    }
+
+    /// lower is better, discrete first unless asked for low power
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rank(&self, device_type: wgpu::DeviceType) -> u8 {
+        match (self.power, device_type) {
+            (PowerPreference::LowPower, wgpu::DeviceType::IntegratedGpu) => 0,
+            (PowerPreference::LowPower, wgpu::DeviceType::DiscreteGpu) => 1,
+            (_, wgpu::DeviceType::DiscreteGpu) => 0,
+            (_, wgpu::DeviceType::IntegratedGpu) => 1,
+            (_, wgpu::DeviceType::VirtualGpu) => 2,
+            (_, wgpu::DeviceType::Other) => 3,
+            (_, wgpu::DeviceType::Cpu) => 4,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn accepts(&self, info: &wgpu::AdapterInfo) -> bool {
+        let name_matches = self
+            .name
+            .as_ref()
+            .is_none_or(|name| info.name.to_lowercase().contains(&name.to_lowercase()));
+        name_matches && (self.allow_fallback || info.device_type != wgpu::DeviceType::Cpu)
+    }
+
+    /// matching adapters best first, only the best one unless `multi_adapter`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn select(&self, instance: &wgpu::Instance) -> Result<Vec<wgpu::Adapter>, GpuError> {
+        let mut adapters: Vec<wgpu::Adapter> = instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .into_iter()
+            .filter(|adapter| self.accepts(&adapter.get_info()))
+            .collect();
+        adapters.sort_by_key(|adapter| self.rank(adapter.get_info().device_type));
+        // a software adapter only stands in when there is nothing else
+        if adapters.iter().any(|adapter| adapter.get_info().device_type != wgpu::DeviceType::Cpu) {
+            adapters.retain(|adapter| adapter.get_info().device_type != wgpu::DeviceType::Cpu);
+        }
+        if !self.multi_adapter {
+            adapters.truncate(1);
+        }
+
+        if adapters.is_empty() {
+            return Err(GpuError::NoAdapter);
+        }
+        Ok(adapters)
+    }
+
+    /// a browser exposes a single adapter, the fallback one is only asked for when that fails
+    #[cfg(target_arch = "wasm32")]
+    pub async fn select(&self, instance: &wgpu::Instance) -> Result<Vec<wgpu::Adapter>, GpuError> {
+        let mut options = wgpu::RequestAdapterOptions { power_preference: self.power.wgpu(), ..Default::default() };
+        let mut adapter = instance.request_adapter(&options).await;
+        if adapter.is_err() && self.allow_fallback {
+            options.force_fallback_adapter = true;
+            adapter = instance.request_adapter(&options).await;
+        }
+        Ok(vec![adapter.map_err(|_| GpuError::NoAdapter)?])
+    }
+}
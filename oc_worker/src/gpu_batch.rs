@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_dtype::DType;
+use crate::gpu_elementwise::ElementwiseParams;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::{element_count, matrix_dims, HandleSpace};
+
+/// one recorded dispatch, every operand is a tensor handle
+enum BatchOp {
+    Add { a: u64, b: u64, out: u64 },
+    MatMul { a: u64, b: u64, out: u64 },
+    VecMatMul { v: u64, m: u64, out: u64 },
+}
+
+/// staging buffer of a requested read with the dtype and length of its tensor
+type Readback = (PooledBuffer, DType, usize);
+
+/// lazily recorded ops, nothing reaches the gpu until `batch_submit`
+#[derive(Default)]
+pub struct GpuBatch {
+    ops: Vec<BatchOp>,
+    reads: Vec<u64>,
+}
+
+pub struct BatchStore {
+    ids: HandleSpace,
+    batches: HashMap<u64, GpuBatch>,
+}
+
+impl BatchStore {
+    pub fn new() -> Self {
+        Self { ids: HandleSpace::new(), batches: HashMap::new() }
+    }
+
+    fn get_mut(&mut self, id: u64) -> Result<&mut GpuBatch, GpuError> {
+        self.batches.get_mut(&id).ok_or_else(|| self.ids.missing("batch", id))
+    }
+
+    fn remove(&mut self, id: u64) -> Result<GpuBatch, GpuError> {
+        self.batches.remove(&id).ok_or_else(|| self.ids.missing("batch", id))
+    }
+
+    pub fn clear(&mut self) {
+        self.batches.clear();
+    }
+}
+
+impl GpuManager {
+    pub fn batch_new(&self) -> u64 {
+        let mut store = self.batches.lock().unwrap();
+        let id = store.ids.next_id();
+        store.batches.insert(id, GpuBatch::default());
+        id
+    }
+
+    fn batch_push(&self, batch: u64, op: BatchOp) -> Result<(), GpuError> {
+        let mut store = self.batches.lock().unwrap();
+        let batch = store.get_mut(batch)?;
+        batch.ops.push(op);
+        Ok(())
+    }
+
+    /// output tensors are allocated while recording so later ops can consume them,
+    /// their contents are undefined until the batch is submitted
+    fn batch_output(&self, shape: Vec<u32>, dtype: DType) -> Result<u64, GpuError> {
+        let buffer = self.alloc_tensor(&shape, dtype)?;
+        Ok(self.tensors.lock().unwrap().insert(buffer, shape, dtype))
+    }
+
+    /// dtype of the operands, also makes sure the kernel for it exists before recording
+    fn batch_dtype(&self, base: &str, a: u64, b: u64) -> Result<DType, GpuError> {
+        let dtype = self.tensor_dtype(a)?;
+        self.tensor(b, dtype)?;
+        self.typed_kernel(base, dtype)?;
+        Ok(dtype)
+    }
+
+    pub fn batch_add(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        let dtype = self.batch_dtype("add", a, b)?;
+        let shape_a = self.tensor_shape(a)?;
+        let shape_b = self.tensor_shape(b)?;
+        if shape_a != shape_b {
+            return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", shape_a, shape_b)));
+        }
+
+        let out = self.batch_output(shape_a, dtype)?;
+        self.batch_push(batch, BatchOp::Add { a, b, out })?;
+        Ok(out)
+    }
+
+    pub fn batch_matmul(&self, batch: u64, a: u64, b: u64) -> Result<u64, GpuError> {
+        let dtype = self.batch_dtype("matrix_multiply", a, b)?;
+        let (a_long, a_width) = matrix_dims(&self.tensor_shape(a)?)?;
+        let (b_long, b_width) = matrix_dims(&self.tensor_shape(b)?)?;
+        if a_width != b_long {
+            return Err(GpuError::ShapeMismatch("Matrix A's width must equal Matrix B's height.".into()));
+        }
+
+        let out = self.batch_output(vec![a_long, b_width], dtype)?;
+        self.batch_push(batch, BatchOp::MatMul { a, b, out })?;
+        Ok(out)
+    }
+
+    pub fn batch_vec_matmul(&self, batch: u64, v: u64, m: u64) -> Result<u64, GpuError> {
+        let dtype = self.batch_dtype("vector_matrix_multiply", v, m)?;
+        let shape_v = self.tensor_shape(v)?;
+        let (m_long, m_width) = matrix_dims(&self.tensor_shape(m)?)?;
+        if element_count(&shape_v)? != m_long {
+            return Err(GpuError::ShapeMismatch("Vector A's width must equal Matrix B's height.".into()));
+        }
+
+        let out = self.batch_output(vec![m_width], dtype)?;
+        self.batch_push(batch, BatchOp::VecMatMul { v, m, out })?;
+        Ok(out)
+    }
+
+    /// ask for `tensor` to be copied back when the batch is submitted
+    pub fn batch_read(&self, batch: u64, tensor: u64) -> Result<(), GpuError> {
+        self.tensor_shape(tensor)?;
+
+        let mut store = self.batches.lock().unwrap();
+        let batch = store.get_mut(batch)?;
+        batch.reads.push(tensor);
+        Ok(())
+    }
+
+    /// record every op into `encoder` followed by the copies of the requested reads,
+    /// returns the buffers that must live until submit and the staging buffers with their lengths
+    fn batch_encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        batch: &GpuBatch,
+    ) -> Result<(Vec<Arc<PooledBuffer>>, Vec<Readback>), GpuError> {
+        let mut keep_alive = Vec::new();
+
+        for op in batch.ops.iter() {
+            match *op {
+                BatchOp::Add { a, b, out } => {
+                    let dtype = self.tensor_dtype(a)?;
+                    let (buffer_a, shape) = self.tensor(a, dtype)?;
+                    let (buffer_b, _) = self.tensor(b, dtype)?;
+                    let (buffer_out, _) = self.tensor(out, dtype)?;
+                    let len = element_count(&shape)?;
+
+                    let params = self.encode_elementwise(
+                        encoder,
+                        &dtype.kernel_name("add"),
+                        &[&*buffer_a, &*buffer_b],
+                        &buffer_out,
+                        ElementwiseParams::new(len, 0.0, 0.0),
+                        dtype.size(),
+                    )?;
+                    keep_alive.extend([buffer_a, buffer_b, buffer_out]);
+                    keep_alive.extend(params.into_iter().map(Arc::new));
+                }
+                BatchOp::MatMul { a, b, out } => {
+                    let dtype = self.tensor_dtype(a)?;
+                    let (buffer_a, shape_a) = self.tensor(a, dtype)?;
+                    let (buffer_b, shape_b) = self.tensor(b, dtype)?;
+                    let (buffer_out, _) = self.tensor(out, dtype)?;
+                    let (a_long, a_width) = matrix_dims(&shape_a)?;
+                    let (b_long, b_width) = matrix_dims(&shape_b)?;
+                    let a_header = self.header_buffer(a_long, a_width)?;
+                    let b_header = self.header_buffer(b_long, b_width)?;
+
+                    self.encode_dispatch(encoder, &dtype.kernel_name("matrix_multiply"), 0, (b_width.div_ceil(16), a_long.div_ceil(16), 1), &[
+                        wgpu::BindGroupEntry { binding: 0, resource: a_header.binding() },
+                        wgpu::BindGroupEntry { binding: 1, resource: b_header.binding() },
+                        wgpu::BindGroupEntry { binding: 2, resource: buffer_a.binding() },
+                        wgpu::BindGroupEntry { binding: 3, resource: buffer_b.binding() },
+                        wgpu::BindGroupEntry { binding: 4, resource: buffer_out.binding() },
+                    ])?;
+                    keep_alive.extend([buffer_a, buffer_b, buffer_out, Arc::new(a_header), Arc::new(b_header)]);
+                }
+                BatchOp::VecMatMul { v, m, out } => {
+                    let dtype = self.tensor_dtype(v)?;
+                    let (buffer_v, _) = self.tensor(v, dtype)?;
+                    let (buffer_m, shape_m) = self.tensor(m, dtype)?;
+                    let (buffer_out, _) = self.tensor(out, dtype)?;
+                    let (m_long, m_width) = matrix_dims(&shape_m)?;
+                    let header = self.header_buffer(m_long, m_width)?;
+
+                    self.encode_dispatch(encoder, &dtype.kernel_name("vector_matrix_multiply"), 0, self.grid_1d(m_width, 64), &[
+                        wgpu::BindGroupEntry { binding: 0, resource: buffer_v.binding() },
+                        wgpu::BindGroupEntry { binding: 1, resource: header.binding() },
+                        wgpu::BindGroupEntry { binding: 2, resource: buffer_m.binding() },
+                        wgpu::BindGroupEntry { binding: 3, resource: buffer_out.binding() },
+                    ])?;
+                    keep_alive.extend([buffer_v, buffer_m, buffer_out, Arc::new(header)]);
+                }
+            }
+        }
+
+        let mut readbacks = Vec::with_capacity(batch.reads.len());
+        for tensor in batch.reads.iter() {
+            let dtype = self.tensor_dtype(*tensor)?;
+            let (buffer, shape) = self.tensor(*tensor, dtype)?;
+            let len = element_count(&shape)? as u64;
+
+            let staging = self.acquire_buffer(
+                (len * dtype.size()).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            )?;
+            encoder.copy_buffer_to_buffer(&buffer, 0, &staging, 0, staging.byte_len());
+            readbacks.push((staging, dtype, len as usize));
+        }
+
+        Ok((keep_alive, readbacks))
+    }
+
+    /// record every op into one encoder, copy the requested reads into staging buffers,
+    /// submit once and return the reads, widened to f32, in the order they were asked for
+    pub async fn batch_submit(&self, batch: u64) -> Result<Vec<Vec<f32>>, GpuError> {
+        let batch = self.batches.lock().unwrap().remove(batch)?;
+
+        let (keep_alive, readbacks) = self
+            .scoped(|| {
+                let mut encoder =
+                    self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("gpu_batch") });
+                let encoded = self.batch_encode(&mut encoder, &batch)?;
+                self.queue.submit(Some(encoder.finish()));
+                Ok(encoded)
+            })
+            .await?;
+
+        drop(keep_alive);
+
+        // map every staging buffer before waiting on any of them
+        futures::future::try_join_all(readbacks.iter().map(|(staging, _, _)| self.map_read(staging))).await?;
+
+        let mut results = Vec::with_capacity(readbacks.len());
+        for (staging, dtype, len) in readbacks.iter() {
+            let slice = staging.slice(..staging.byte_len());
+            let data = slice.get_mapped_range();
+            let mut result = dtype.decode(&data);
+            result.truncate(*len);
+
+            drop(data);
+            staging.unmap();
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+}
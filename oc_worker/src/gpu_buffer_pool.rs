@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::gpu_error::GpuError;
+
+// smallest bucket, keeps tiny header/struct buffers from fragmenting the pool
+const MIN_BUCKET_SIZE: u64 = 256;
+// upper end of the cap derived from the device limits
+const MAX_DEFAULT_CAP_BYTES: u64 = 4 << 30;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoolStats {
+    pub allocated_bytes: u64,
+    pub in_use_bytes: u64,
+    pub free_bytes: u64,
+    pub cap_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// size bucketed free lists of storage and staging buffers, keyed by usage
+pub struct BufferPool {
+    free: HashMap<(u32, u64), Vec<wgpu::Buffer>>,
+    stats: PoolStats,
+    /// `max_buffer_size` of the device, no bucket is larger
+    max_buffer_size: u64,
+}
+
+impl BufferPool {
+    /// pool for a device with `limits`, total bytes (in use + cached) are capped at room for
+    /// four of its largest buffers, an op's inputs and output, but at most 4 GiB
+    pub fn new(limits: &wgpu::Limits) -> Self {
+        let cap_bytes = limits.max_buffer_size.saturating_mul(4).min(MAX_DEFAULT_CAP_BYTES);
+        Self {
+            free: HashMap::new(),
+            stats: PoolStats { cap_bytes, ..Default::default() },
+            max_buffer_size: limits.max_buffer_size,
+        }
+    }
+
+    /// change the cap, cached buffers above it are destroyed right away and buffers in use
+    /// when they come back
+    pub fn set_cap(&mut self, cap_bytes: u64) {
+        self.stats.cap_bytes = cap_bytes;
+        let over = self.stats.allocated_bytes.saturating_sub(cap_bytes);
+        if over > 0 {
+            self.evict(over);
+        }
+    }
+
+    /// power of two buckets up to the device limit, exact sizes above it
+    fn bucket_size(&self, size: u64) -> Result<u64, GpuError> {
+        if size > self.max_buffer_size {
+            return Err(GpuError::InvalidArgument(format!(
+                "buffer of {} bytes exceeds the device limit of {}",
+                size, self.max_buffer_size
+            )));
+        }
+        let bucket = size.max(MIN_BUCKET_SIZE).next_power_of_two();
+        if bucket > self.max_buffer_size {
+            return Ok(size.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT));
+        }
+        Ok(bucket)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    /// take a buffer of at least `size` bytes, reusing a cached one of the same bucket if possible
+    pub fn acquire(
+        pool: &Arc<Mutex<BufferPool>>,
+        device: &wgpu::Device,
+        size: u64,
+        usage: wgpu::BufferUsages,
+    ) -> Result<PooledBuffer, GpuError> {
+        // zero sized bindings are invalid, keep one element
+        let size = size.max(4);
+        let mut inner = pool.lock().unwrap();
+        let bucket = inner.bucket_size(size)?;
+        let key = (usage.bits(), bucket);
+
+        let reused = inner.free.get_mut(&key).and_then(|list| list.pop());
+        let buffer = match reused {
+            Some(buffer) => {
+                inner.stats.free_bytes -= bucket;
+                inner.stats.hits += 1;
+                buffer
+            }
+            None => {
+                let over = (inner.stats.allocated_bytes + bucket).saturating_sub(inner.stats.cap_bytes);
+                if over > 0 {
+                    inner.evict(over);
+                }
+                if inner.stats.allocated_bytes + bucket > inner.stats.cap_bytes {
+                    return Err(GpuError::PoolExhausted {
+                        in_use: inner.stats.in_use_bytes,
+                        requested: bucket,
+                        cap: inner.stats.cap_bytes,
+                    });
+                }
+
+                inner.stats.allocated_bytes += bucket;
+                inner.stats.misses += 1;
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("pooled_buffer"),
+                    size: bucket,
+                    usage,
+                    mapped_at_creation: false,
+                })
+            }
+        };
+
+        inner.stats.in_use_bytes += bucket;
+
+        Ok(PooledBuffer {
+            buffer: Some(buffer),
+            size,
+            bucket,
+            usage,
+            map_pending: AtomicBool::new(false),
+            pool: pool.clone(),
+        })
+    }
+
+    fn release(&mut self, buffer: wgpu::Buffer, bucket: u64, usage: wgpu::BufferUsages) {
+        self.stats.in_use_bytes -= bucket;
+
+        // the cap was lowered while the buffer was in use
+        if self.stats.allocated_bytes > self.stats.cap_bytes {
+            self.stats.allocated_bytes -= bucket;
+            buffer.destroy();
+            return;
+        }
+
+        self.stats.free_bytes += bucket;
+        self.free.entry((usage.bits(), bucket)).or_default().push(buffer);
+    }
+
+    /// destroy a buffer that cannot be reused
+    fn discard(&mut self, buffer: wgpu::Buffer, bucket: u64) {
+        self.stats.in_use_bytes -= bucket;
+        self.stats.allocated_bytes -= bucket;
+        buffer.destroy();
+    }
+
+    /// destroy cached buffers until at least `bytes` are freed, largest buckets first
+    fn evict(&mut self, bytes: u64) {
+        let mut keys: Vec<(u32, u64)> = self.free.keys().cloned().collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(key.1));
+
+        let mut freed = 0;
+        for key in keys {
+            if let Some(list) = self.free.get_mut(&key) {
+                while freed < bytes {
+                    match list.pop() {
+                        Some(buffer) => {
+                            buffer.destroy();
+                            freed += key.1;
+                            self.stats.free_bytes -= key.1;
+                            self.stats.allocated_bytes -= key.1;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            if freed >= bytes {
+                break;
+            }
+        }
+        self.free.retain(|_, list| !list.is_empty());
+    }
+
+    /// destroy every cached buffer, buffers still in use are returned to the pool as usual
+    pub fn clear(&mut self) {
+        self.evict(u64::MAX);
+    }
+}
+
+/// a pool buffer that goes back to its free list when dropped
+pub struct PooledBuffer {
+    buffer: Option<wgpu::Buffer>,
+    size: u64,
+    bucket: u64,
+    usage: wgpu::BufferUsages,
+    /// set while a `map_async` has not called back, such a buffer must not be handed out again
+    map_pending: AtomicBool,
+    pool: Arc<Mutex<BufferPool>>,
+}
+
+impl PooledBuffer {
+    /// requested size in bytes, the underlying buffer may be larger
+    pub fn byte_len(&self) -> u64 {
+        self.size
+    }
+
+    /// binding of exactly the requested range
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: self.deref(),
+            offset: 0,
+            size: wgpu::BufferSize::new(self.size),
+        })
+    }
+
+    /// binding of `size` bytes from `offset`, cut at the end of the requested range
+    pub fn binding_range(&self, offset: u64, size: u64) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: self.deref(),
+            offset,
+            size: wgpu::BufferSize::new(size.min(self.size.saturating_sub(offset))),
+        })
+    }
+
+    pub(crate) fn set_map_pending(&self, pending: bool) {
+        self.map_pending.store(pending, Ordering::Relaxed);
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = wgpu::Buffer;
+
+    fn deref(&self) -> &wgpu::Buffer {
+        self.buffer.as_ref().expect("pooled buffer already released")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            // a run dropped while waiting for its readback, the map still completes later
+            if self.map_pending.load(Ordering::Relaxed) {
+                self.pool.lock().unwrap().discard(buffer, self.bucket);
+                return;
+            }
+            self.pool.lock().unwrap().release(buffer, self.bucket, self.usage);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::element_count;
+
+/// (height, width) pairs of a 2d window, pooling ignores `groups`
+#[derive(Debug, Clone, Copy)]
+pub struct Conv2dSpec {
+    pub stride: (u32, u32),
+    pub padding: (u32, u32),
+    pub dilation: (u32, u32),
+    pub groups: u32,
+}
+
+impl Default for Conv2dSpec {
+    fn default() -> Self {
+        Self { stride: (1, 1), padding: (0, 0), dilation: (1, 1), groups: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolMode {
+    Max,
+    Avg,
+}
+
+/// uniform of the conv kernels, mirrors `ConvParams` in gpu_shade
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ConvParams {
+    pub n: u32,
+    pub c: u32,
+    pub h: u32,
+    pub w: u32,
+    pub oc: u32,
+    pub oh: u32,
+    pub ow: u32,
+    pub kh: u32,
+    pub kw: u32,
+    pub sh: u32,
+    pub sw: u32,
+    pub ph: u32,
+    pub pw: u32,
+    pub dh: u32,
+    pub dw: u32,
+    pub groups: u32,
+    pub flag: u32,
+    _pad: [u32; 3],
+}
+
+fn nchw(name: &str, shape: &[u32]) -> Result<(u32, u32, u32, u32), GpuError> {
+    match shape {
+        [n, c, h, w] => Ok((*n, *c, *h, *w)),
+        _ => Err(GpuError::ShapeMismatch(format!("{} must be n x c x h x w, got shape {:?}", name, shape))),
+    }
+}
+
+/// output size along one axis
+fn out_dim(input: u32, kernel: u32, stride: u32, padding: u32, dilation: u32) -> Result<u32, GpuError> {
+    if stride == 0 || dilation == 0 || kernel == 0 {
+        return Err(GpuError::InvalidArgument("kernel size, stride and dilation must be at least 1".into()));
+    }
+    let span = dilation as u64 * (kernel as u64 - 1) + 1;
+    let padded = input as u64 + 2 * padding as u64;
+    if span > padded {
+        return Err(GpuError::ShapeMismatch(format!(
+            "window of {} does not fit an input of {} with padding {}",
+            span, input, padding
+        )));
+    }
+    Ok(((padded - span) / stride as u64 + 1) as u32)
+}
+
+impl Conv2dSpec {
+    /// params for an input of `(n, c, h, w)` and a `(kh, kw)` window
+    fn params(&self, (n, c, h, w): (u32, u32, u32, u32), (kh, kw): (u32, u32)) -> Result<ConvParams, GpuError> {
+        Ok(ConvParams {
+            n,
+            c,
+            h,
+            w,
+            oc: c,
+            oh: out_dim(h, kh, self.stride.0, self.padding.0, self.dilation.0)?,
+            ow: out_dim(w, kw, self.stride.1, self.padding.1, self.dilation.1)?,
+            kh,
+            kw,
+            sh: self.stride.0,
+            sw: self.stride.1,
+            ph: self.padding.0,
+            pw: self.padding.1,
+            dh: self.dilation.0,
+            dw: self.dilation.1,
+            groups: self.groups,
+            ..Default::default()
+        })
+    }
+}
+
+/// input coordinate of kernel tap `k` for output `o`, `None` when it falls into the padding,
+/// same as `tap` in the wgsl
+pub(crate) fn tap(o: u32, k: u32, stride: u32, dilation: u32, pad: u32, size: u32) -> Option<usize> {
+    let at = (o * stride + k * dilation) as i64 - pad as i64;
+    (at >= 0 && at < size as i64).then_some(at as usize)
+}
+
+/// params and output shape of a conv2d, `bias` is the shape of the bias tensor if any
+pub(crate) fn conv2d_params(
+    spec: &Conv2dSpec,
+    shape_x: &[u32],
+    shape_w: &[u32],
+    bias: Option<&[u32]>,
+) -> Result<(ConvParams, Vec<u32>), GpuError> {
+    let dims = nchw("conv2d input", shape_x)?;
+    let (oc, cpg, kh, kw) = nchw("conv2d weight", shape_w)?;
+    let groups = spec.groups;
+    if groups == 0 || dims.1 % groups != 0 || oc % groups != 0 {
+        return Err(GpuError::InvalidArgument(format!(
+            "groups {} must divide the {} input and {} output channels",
+            groups, dims.1, oc
+        )));
+    }
+    if cpg != dims.1 / groups {
+        return Err(GpuError::ShapeMismatch(format!(
+            "weight has {} channels per group, the input {}",
+            cpg,
+            dims.1 / groups
+        )));
+    }
+    if let Some(shape) = bias {
+        if element_count(shape)? != oc {
+            return Err(GpuError::ShapeMismatch(format!("bias has shape {:?}, expected {} values", shape, oc)));
+        }
+    }
+
+    let mut params = spec.params(dims, (kh, kw))?;
+    params.oc = oc;
+    params.flag = bias.is_some() as u32;
+    let out_shape = vec![dims.0, oc, params.oh, params.ow];
+    element_count(&out_shape)?;
+    Ok((params, out_shape))
+}
+
+/// params and output shape of a max or average pooling
+pub(crate) fn pool2d_params(
+    spec: &Conv2dSpec,
+    shape_x: &[u32],
+    mode: PoolMode,
+    kernel: (u32, u32),
+) -> Result<(ConvParams, Vec<u32>), GpuError> {
+    let dims = nchw("pool2d input", shape_x)?;
+    if spec.padding.0 * 2 > kernel.0 || spec.padding.1 * 2 > kernel.1 {
+        return Err(GpuError::InvalidArgument("pool2d padding must be at most half the window".into()));
+    }
+
+    let mut params = spec.params(dims, kernel)?;
+    params.flag = (mode == PoolMode::Avg) as u32;
+    let out_shape = vec![dims.0, dims.1, params.oh, params.ow];
+    element_count(&out_shape)?;
+    Ok((params, out_shape))
+}
+
+/// params and output shape of an im2col
+pub(crate) fn im2col_params(spec: &Conv2dSpec, shape_x: &[u32], kernel: (u32, u32)) -> Result<(ConvParams, Vec<u32>), GpuError> {
+    let dims = nchw("im2col input", shape_x)?;
+
+    let params = spec.params(dims, kernel)?;
+    let rows = element_count(&[dims.1, kernel.0, kernel.1])?;
+    let out_shape = vec![dims.0, rows, element_count(&[params.oh, params.ow])?];
+    element_count(&out_shape)?;
+    Ok((params, out_shape))
+}
+
+impl GpuManager {
+    /// run a built-in conv kernel, one invocation per value of `out_shape`:
+    /// binding 0 is the params, then `inputs`, then the output
+    async fn run_conv(
+        &self,
+        kernel: &str,
+        params: ConvParams,
+        inputs: &[&PooledBuffer],
+        out_shape: Vec<u32>,
+    ) -> Result<u64, GpuError> {
+        let total = element_count(&out_shape)?;
+        let params_buffer = self.uniform_buffer(&params)?;
+        let result = self.alloc_tensor(&out_shape, DType::F32)?;
+
+        {
+            let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: params_buffer.binding() }];
+            for (i, input) in inputs.iter().enumerate() {
+                entries.push(wgpu::BindGroupEntry { binding: i as u32 + 1, resource: input.binding() });
+            }
+            entries.push(wgpu::BindGroupEntry { binding: inputs.len() as u32 + 1, resource: result.binding() });
+
+            self.dispatch(kernel, 0, self.grid_1d(total, 64), &entries).await?;
+        }
+
+        Ok(self.tensors.lock().unwrap().insert(result, out_shape, DType::F32))
+    }
+
+    /// x: n x c x h x w, weight: oc x c/groups x kh x kw, bias: oc values
+    pub async fn tensor_conv2d(&self, x: u64, weight: u64, bias: Option<u64>, spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (buffer_x, shape_x) = self.tensor(x, DType::F32)?;
+        let (buffer_w, shape_w) = self.tensor(weight, DType::F32)?;
+        let bias = bias.map(|id| self.tensor(id, DType::F32)).transpose()?;
+        let (params, out_shape) = conv2d_params(spec, &shape_x, &shape_w, bias.as_ref().map(|(_, shape)| shape.as_slice()))?;
+
+        // without a bias the weight stands in for its binding, it is never read
+        let bias_buffer = bias.as_ref().map_or(&buffer_w, |(buffer, _)| buffer);
+        self.run_conv("conv2d", params, &[&*buffer_x, &*buffer_w, &**bias_buffer], out_shape).await
+    }
+
+    /// max or average over each window, padding is skipped
+    pub async fn tensor_pool2d(&self, x: u64, mode: PoolMode, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (buffer_x, shape_x) = self.tensor(x, DType::F32)?;
+        let (params, out_shape) = pool2d_params(spec, &shape_x, mode, kernel)?;
+
+        self.run_conv("pool2d", params, &[&*buffer_x], out_shape).await
+    }
+
+    /// n x (c * kh * kw) x (oh * ow) columns, a conv2d is then a gemm with the flattened weight
+    pub async fn tensor_im2col(&self, x: u64, kernel: (u32, u32), spec: &Conv2dSpec) -> Result<u64, GpuError> {
+        let (buffer_x, shape_x) = self.tensor(x, DType::F32)?;
+        let (params, out_shape) = im2col_params(spec, &shape_x, kernel)?;
+
+        self.run_conv("im2col", params, &[&*buffer_x], out_shape).await
+    }
+}
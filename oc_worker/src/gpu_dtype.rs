@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// element type of a gpu buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DType {
+    #[default]
+    F32,
+    /// needs `SHADER_F16` on the device
+    F16,
+    U32,
+    I32,
+    /// quantized data, packed four to a u32 on the gpu
+    U8,
+}
+
+impl DType {
+    pub fn size(&self) -> u64 {
+        match self {
+            DType::F32 | DType::U32 | DType::I32 => 4,
+            DType::F16 => 2,
+            DType::U8 => 1,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DType::F32 => "f32",
+            DType::F16 => "f16",
+            DType::U32 => "u32",
+            DType::I32 => "i32",
+            DType::U8 => "u8",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "f32" => Ok(DType::F32),
+            "f16" => Ok(DType::F16),
+            "u32" => Ok(DType::U32),
+            "i32" => Ok(DType::I32),
+            "u8" => Ok(DType::U8),
+            _ => Err(format!("unsupported dtype {}", name)),
+        }
+    }
+
+    /// wgsl scalar type, `None` for u8 which has no arithmetic kernels of its own
+    pub fn wgsl_type(&self) -> Option<&'static str> {
+        match self {
+            DType::U8 => None,
+            _ => Some(self.name()),
+        }
+    }
+
+    /// pipeline name of the `base` kernel for this dtype, f32 keeps the plain name
+    pub fn kernel_name(&self, base: &str) -> String {
+        match self {
+            DType::F32 => base.to_string(),
+            _ => format!("{}_{}", base, self.name()),
+        }
+    }
+
+    /// host values to the little endian bytes the kernels read
+    pub fn encode(&self, data: &[f32]) -> Vec<u8> {
+        match self {
+            DType::F32 => bytemuck::cast_slice(data).to_vec(),
+            DType::F16 => data.iter().flat_map(|v| half::f16::from_f32(*v).to_le_bytes()).collect(),
+            DType::U32 => data.iter().flat_map(|v| (*v as u32).to_le_bytes()).collect(),
+            DType::I32 => data.iter().flat_map(|v| (*v as i32).to_le_bytes()).collect(),
+            DType::U8 => data.iter().map(|v| v.round().clamp(0.0, 255.0) as u8).collect(),
+        }
+    }
+
+    /// inverse of `encode`, ignores trailing padding
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        match self {
+            DType::F32 => bytes.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+            DType::F16 => bytes.chunks_exact(2).map(|c| half::f16::from_le_bytes([c[0], c[1]]).to_f32()).collect(),
+            DType::U32 => bytes.chunks_exact(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32).collect(),
+            DType::I32 => bytes.chunks_exact(4).map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32).collect(),
+            DType::U8 => bytes.iter().map(|b| *b as f32).collect(),
+        }
+    }
+}
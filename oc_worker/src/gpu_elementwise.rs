@@ -0,0 +1,173 @@
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::element_count;
+
+/// invocations per workgroup of every elementwise kernel, matches ELEMENTWISE_SOURCE
+pub(crate) const ELEMENTWISE_WORKGROUP_SIZE: u32 = 256;
+
+/// ops of the elementwise family, all operands have the same shape and dtype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementwiseOp {
+    Add,
+    Sub,
+    Mul,
+    /// integer tensors divide by zero into `a`, as wgsl defines it
+    Div,
+    /// `a * b + c`
+    Fma,
+    /// `a * alpha + beta`
+    Scale,
+    /// `a` clamped to `[alpha, beta]`
+    Clamp,
+}
+
+/// uniform of the elementwise kernels, mirrors `ElementwiseParams` in gpu_shade
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ElementwiseParams {
+    len: u32,
+    _pad: u32,
+    alpha: f32,
+    beta: f32,
+}
+
+impl ElementwiseParams {
+    pub(crate) fn new(len: u32, alpha: f32, beta: f32) -> Self {
+        Self { len, _pad: 0, alpha, beta }
+    }
+}
+
+impl ElementwiseOp {
+    pub fn kernel(&self) -> &'static str {
+        match self {
+            ElementwiseOp::Add => "add",
+            ElementwiseOp::Sub => "sub",
+            ElementwiseOp::Mul => "mul",
+            ElementwiseOp::Div => "div",
+            ElementwiseOp::Fma => "mul_add",
+            ElementwiseOp::Scale => "scale",
+            ElementwiseOp::Clamp => "clamp_range",
+        }
+    }
+
+    /// number of tensor operands
+    pub fn arity(&self) -> usize {
+        match self {
+            ElementwiseOp::Fma => 3,
+            ElementwiseOp::Scale | ElementwiseOp::Clamp => 1,
+            _ => 2,
+        }
+    }
+
+    /// the f32 kernel on the host, `x` holds one value per operand
+    pub(crate) fn apply(&self, x: &[f32], alpha: f32, beta: f32) -> f32 {
+        match self {
+            ElementwiseOp::Add => x[0] + x[1],
+            ElementwiseOp::Sub => x[0] - x[1],
+            ElementwiseOp::Mul => x[0] * x[1],
+            ElementwiseOp::Div => x[0] / x[1],
+            ElementwiseOp::Fma => x[0] * x[1] + x[2],
+            ElementwiseOp::Scale => x[0] * alpha + beta,
+            ElementwiseOp::Clamp => x[0].max(alpha).min(beta),
+        }
+    }
+
+    /// operand count and scalars, shared by the backends
+    pub(crate) fn check(&self, given: usize, alpha: f32, beta: f32) -> Result<(), GpuError> {
+        if *self == ElementwiseOp::Clamp && alpha > beta {
+            return Err(GpuError::InvalidArgument(format!("clamp range {}..{} is empty", alpha, beta)));
+        }
+        if given != self.arity() {
+            return Err(GpuError::InvalidArgument(format!(
+                "{} takes {} tensors, got {}",
+                self.kernel(),
+                self.arity(),
+                given
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl GpuManager {
+    /// record `kernel` over `params.len` values of `elem_size` bytes into `encoder`, split into
+    /// dispatches over bound ranges when the operands are over the binding limit,
+    /// the returned params buffers must live until submit
+    pub(crate) fn encode_elementwise(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        kernel: &str,
+        inputs: &[&PooledBuffer],
+        out: &PooledBuffer,
+        params: ElementwiseParams,
+        elem_size: u64,
+    ) -> Result<Vec<PooledBuffer>, GpuError> {
+        let chunk = self.binding_chunk(elem_size);
+        let mut keep = Vec::new();
+        let mut start = 0;
+        while start < params.len as u64 {
+            let len = chunk.min(params.len as u64 - start);
+            // storage bindings are whole words, f16 tails round up into the buffer padding
+            let (offset, size) = (start * elem_size, (len * elem_size).next_multiple_of(4));
+            let params_buffer = self.uniform_buffer(&ElementwiseParams { len: len as u32, ..params })?;
+
+            let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: params_buffer.binding() }];
+            for (i, input) in inputs.iter().enumerate() {
+                entries.push(wgpu::BindGroupEntry { binding: i as u32 + 1, resource: input.binding_range(offset, size) });
+            }
+            // the output sits after the widest op so one module serves the whole family
+            entries.push(wgpu::BindGroupEntry { binding: 4, resource: out.binding_range(offset, size) });
+
+            self.encode_dispatch(encoder, kernel, 0, self.grid_1d(len as u32, ELEMENTWISE_WORKGROUP_SIZE), &entries)?;
+            keep.push(params_buffer);
+            start += len;
+        }
+        Ok(keep)
+    }
+
+    /// `op` over resident tensors, the result is a new tensor of the same shape and dtype,
+    /// the scalars are converted to that dtype
+    pub async fn tensor_elementwise(&self, op: ElementwiseOp, inputs: &[u64], alpha: f32, beta: f32) -> Result<u64, GpuError> {
+        op.check(inputs.len(), alpha, beta)?;
+        let dtype = self.tensor_dtype(inputs[0])?;
+        let mut buffers = Vec::with_capacity(inputs.len());
+        let mut shape = None;
+        for &id in inputs {
+            let (buffer, tensor_shape) = self.tensor(id, dtype)?;
+            match &shape {
+                Some(first) if *first != tensor_shape => {
+                    return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", first, tensor_shape)));
+                }
+                Some(_) => {}
+                None => shape = Some(tensor_shape),
+            }
+            buffers.push(buffer);
+        }
+        let shape = shape.unwrap_or_default();
+
+        let kernel = self.typed_kernel(op.kernel(), dtype)?;
+        let len = element_count(&shape)?;
+        let result = self.alloc_tensor(&shape, dtype)?;
+        let inputs: Vec<&PooledBuffer> = buffers.iter().map(|buffer| &**buffer).collect();
+
+        let params = self
+            .scoped(|| {
+                let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                let params = self.encode_elementwise(
+                    &mut encoder,
+                    &kernel,
+                    &inputs,
+                    &result,
+                    ElementwiseParams::new(len, alpha, beta),
+                    dtype.size(),
+                )?;
+                self.queue.submit(Some(encoder.finish()));
+                Ok(params)
+            })
+            .await?;
+        drop(params);
+
+        Ok(self.tensors.lock().unwrap().insert(result, shape, dtype))
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// everything the gpu side can fail with, a bad job input must end up here instead of a panic
+#[derive(Debug, Clone)]
+pub enum GpuError {
+    NoAdapter,
+    RequestDevice(String),
+    NotInitialized,
+    /// the device is gone, every later call fails with this until the worker restarts
+    DeviceLost(String),
+    /// captured from a validation error scope
+    Validation(String),
+    /// captured from an out of memory error scope
+    OutOfMemory(String),
+    PoolExhausted { in_use: u64, requested: u64, cap: u64 },
+    MapFailed(String),
+    ShapeMismatch(String),
+    InvalidArgument(String),
+    InvalidHandle { kind: &'static str, id: u64 },
+    /// a handle issued by another device or backend
+    ForeignHandle { kind: &'static str, id: u64 },
+    UnknownPipeline(String),
+    /// wgsl that does not parse, validate or reflect
+    Shader(String),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::NoAdapter => write!(f, "no suitable gpu adapter"),
+            GpuError::RequestDevice(e) => write!(f, "failed to create gpu device: {}", e),
+            GpuError::NotInitialized => write!(f, "gpu not initialized"),
+            GpuError::DeviceLost(e) => write!(f, "gpu device lost: {}", e),
+            GpuError::Validation(e) => write!(f, "gpu validation error: {}", e),
+            GpuError::OutOfMemory(e) => write!(f, "gpu out of memory: {}", e),
+            GpuError::PoolExhausted { in_use, requested, cap } => write!(
+                f,
+                "gpu buffer pool cap exceeded: {} in use, {} requested, cap {}",
+                in_use, requested, cap
+            ),
+            GpuError::MapFailed(e) => write!(f, "gpu buffer map failed: {}", e),
+            GpuError::ShapeMismatch(e) => write!(f, "shape mismatch: {}", e),
+            GpuError::InvalidArgument(e) => write!(f, "{}", e),
+            GpuError::InvalidHandle { kind, id } => write!(f, "invalid {} handle {}", kind, id),
+            GpuError::ForeignHandle { kind, id } => write!(f, "{} handle {} belongs to another device", kind, id),
+            GpuError::UnknownPipeline(name) => write!(f, "no pipeline {}", name),
+            GpuError::Shader(e) => write!(f, "shader error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+impl From<wgpu::Error> for GpuError {
+    fn from(e: wgpu::Error) -> Self {
+        match e {
+            wgpu::Error::OutOfMemory { .. } => GpuError::OutOfMemory(e.to_string()),
+            wgpu::Error::Validation { description, .. } => GpuError::Validation(description),
+            other => GpuError::Validation(other.to_string()),
+        }
+    }
+}
+
+/// rune and the protocol only carry strings
+impl From<GpuError> for String {
+    fn from(e: GpuError) -> Self {
+        e.to_string()
+    }
+}
@@ -1,117 +1,94 @@
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_elementwise::{ElementwiseParams, ELEMENTWISE_WORKGROUP_SIZE};
+use crate::gpu_error::GpuError;
+use crate::gpu_gemm::GemmSpec;
 use crate::gpu_init::GpuManager;
+use crate::gpu_profile::now_ms;
 use bytemuck::{Pod, Zeroable};
 use futures_intrusive::channel::shared::oneshot_channel;
-use wgpu::util::DeviceExt;
 
 macro_rules! define_input_struct {
-    ($self_:expr, $data:expr) => {
-        $self_
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::bytes_of(&$data),
-                usage: wgpu::BufferUsages::STORAGE,
-            })
-    };
+    ($self_:expr, $data:expr) => {{
+        // a `let` borrow extends a struct literal's lifetime, a borrow inside the call would not
+        let data = &$data;
+        let contents = bytemuck::bytes_of(data);
+        let buffer = $self_.acquire_buffer(
+            contents.len() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        )?;
+        $self_.queue.write_buffer(&buffer, 0, contents);
+        buffer
+    }};
 }
 
 macro_rules! define_input_array {
-    ($self_:expr, $data:expr) => {
-        $self_
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&$data),
-                usage: wgpu::BufferUsages::STORAGE,
-            })
-    };
-}
-
-macro_rules! define_output_struct {
-    ($self_:expr, $ty:ty) => {
-        $self_.device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: std::mem::size_of::<$ty>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        })
-    };
+    ($self_:expr, $data:expr) => {{
+        let data = &$data;
+        let contents: &[u8] = bytemuck::cast_slice(data);
+        let buffer = $self_.acquire_buffer(
+            contents.len() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        )?;
+        if !contents.is_empty() {
+            $self_.write_timed(&buffer, contents);
+        }
+        buffer
+    }};
 }
 
 macro_rules! define_output_array {
-    ($self_:expr, $len: expr) => {
-        $self_.device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: ($len as u32 * std::mem::size_of::<f32>() as u32) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        })
+    ($self_:expr, $len: expr, $elem: ty) => {
+        $self_.acquire_buffer(
+            ($len as u64) * std::mem::size_of::<$elem>() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        )?
     };
 }
 
-macro_rules! run_gpu_result_struct {
-    ( $self_:expr, $call_func:expr, $group: expr, $result_type: ty, $run_x: expr, $run_y: expr, $run_z: expr, $buffer_result: expr, $( $binding:expr => $buffer:expr ),* $(,)? ) => {
+
+macro_rules! run_gpu_result_array {
+    ( $self_:expr, $call_func:expr, $group: expr, $len: expr, $elem: ty, $run_x: expr, $run_y: expr, $run_z: expr, $buffer_result: expr, $( $binding:expr => $buffer:expr ),* $(,)? ) => {
         {
-            let bind_group = {
-                let entries = &[
+            let result_readback = $self_.acquire_buffer(
+                ($len as u64) * std::mem::size_of::<$elem>() as u64,
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            )?;
+
+            $self_.scoped(|| {
+                let mut encoder = $self_
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: None,
+                    });
+
+                $self_.encode_dispatch(&mut encoder, $call_func, $group, ($run_x, $run_y, $run_z), &[
                     $(
                         wgpu::BindGroupEntry {
                             binding: $binding,
-                            resource: $buffer.as_entire_binding(),
+                            resource: $buffer.binding(),
                         }
                     ),*
-                ];
-                $self_.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &$self_.pipelines[$call_func].get_bind_group_layout($group),
-                    entries,
-                    label: None,
-                })
-            };
+                ])?;
 
-            let mut encoder = $self_
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: None,
-                });
+                encoder.copy_buffer_to_buffer(
+                    &$buffer_result,
+                    0,
+                    &result_readback,
+                    0,
+                    result_readback.byte_len(),
+                );
 
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: None,
-                    timestamp_writes: None,
-                });
-                compute_pass.set_pipeline(&$self_.pipelines[$call_func]);
-                compute_pass.set_bind_group($group, &bind_group, &[]);
-                compute_pass.dispatch_workgroups($run_x, $run_y, $run_z);
-            }
-
-            let result_readback = $self_.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: std::mem::size_of::<$result_type>() as u64,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
+                $self_.queue.submit(Some(encoder.finish()));
+                Ok(())
+            }).await?;
 
-            encoder.copy_buffer_to_buffer(
-                &$buffer_result,
-                0,
-                &result_readback,
-                0,
-                std::mem::size_of::<$result_type>() as u64,
-            );
-
-            $self_.queue.submit(Some(encoder.finish()));
-
-            let slice = result_readback.slice(..);
-            let (sender, receiver) = oneshot_channel();
-            slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
-            receiver
-                .receive()
-                .await
-                .unwrap()
-                .map_err(|_| "map failed")?;
+            $self_.map_read(&result_readback).await?;
 
+            let slice = result_readback.slice(..result_readback.byte_len());
             let data = slice.get_mapped_range();
-            let result = bytemuck::from_bytes::<$result_type>(&data).value;
+            let mut result: Vec<$elem> = bytemuck::cast_slice(&data).to_vec();
+            result.truncate($len as usize);
+
             drop(data);
             result_readback.unmap();
 
@@ -120,118 +97,198 @@ macro_rules! run_gpu_result_struct {
     };
 }
 
-macro_rules! run_gpu_result_array {
-    ( $self_:expr, $call_func:expr, $group: expr, $len: expr, $run_x: expr, $run_y: expr, $run_z: expr, $buffer_result: expr, $( $binding:expr => $buffer:expr ),* $(,)? ) => {
-        {
-            let bind_group = {
-                let entries = &[
-                    $(
-                        wgpu::BindGroupEntry {
-                            binding: $binding,
-                            resource: $buffer.as_entire_binding(),
-                        }
-                    ),*
-                ];
-                $self_.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &$self_.pipelines[$call_func].get_bind_group_layout($group),
-                    entries,
-                    label: None,
-                })
-            };
-
-            let mut encoder = $self_
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: None,
-                });
-
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: None,
-                    timestamp_writes: None,
-                });
-                compute_pass.set_pipeline(&$self_.pipelines[$call_func]);
-                compute_pass.set_bind_group($group, &bind_group, &[]);
-                compute_pass.dispatch_workgroups($run_x, $run_y, $run_z);
-            }
-
-            let result_readback = $self_.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: ($len as u32 * std::mem::size_of::<f32>() as u32) as wgpu::BufferAddress,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
 
-            encoder.copy_buffer_to_buffer(
-                &$buffer_result,
-                0,
-                &result_readback,
-                0,
-                ($len as u32 * std::mem::size_of::<f32>() as u32) as wgpu::BufferAddress,
-            );
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct MatrixHeader {
+    pub long: u32,
+    pub width: u32,
+}
 
-            //for debug log
-            $self_.device.push_error_scope(wgpu::ErrorFilter::Validation);
 
-            $self_.queue.submit(Some(encoder.finish()));
+impl GpuManager {
+    /// record one dispatch of `call_func` into `encoder`
+    pub fn encode_dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        call_func: &str,
+        group: u32,
+        workgroups: (u32, u32, u32),
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<(), GpuError> {
+        self.encode_dispatch_groups(encoder, call_func, workgroups, &[(group, entries)])
+    }
 
-            //for debug log
-            let err = $self_.device.pop_error_scope().await;
-            if let Some(e) = err {
-                log::error!("GPU Validation Error: {:?}", e);
-            }
+    /// like `encode_dispatch` for kernels spreading their bindings over several groups
+    pub fn encode_dispatch_groups(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        call_func: &str,
+        workgroups: (u32, u32, u32),
+        groups: &[(u32, &[wgpu::BindGroupEntry])],
+    ) -> Result<(), GpuError> {
+        let pipeline = self.pipeline(call_func)?;
+
+        let bind_groups: Vec<(u32, wgpu::BindGroup)> = groups
+            .iter()
+            .map(|(group, entries)| {
+                let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pipeline.get_bind_group_layout(*group),
+                    entries,
+                    label: None,
+                });
+                (*group, bind_group)
+            })
+            .collect();
 
-            let slice = result_readback.slice(..);
-            let (sender, receiver) = oneshot_channel();
-            slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
-            receiver
-                .receive()
-                .await
-                .unwrap()
-                .map_err(|_| "map failed")?;
+        let timestamps = self.pass_timestamps();
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: timestamps.as_ref().map(|query_set| wgpu::ComputePassTimestampWrites {
+                query_set,
+                beginning_of_pass_write_index: Some(0),
+                end_of_pass_write_index: Some(1),
+            }),
+        });
+        compute_pass.set_pipeline(&pipeline);
+        for (group, bind_group) in bind_groups.iter() {
+            compute_pass.set_bind_group(*group, bind_group, &[]);
+        }
+        compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+        drop(compute_pass);
 
-            let data = slice.get_mapped_range();
-            let result: Vec<f32> = bytemuck::cast_slice(&data).to_vec();
+        if let Some(query_set) = timestamps {
+            self.resolve_timestamps(encoder, &query_set)?;
+        }
+        Ok(())
+    }
 
-            drop(data);
-            result_readback.unmap();
+    /// small parameter struct bound as `var<uniform>`
+    pub(crate) fn uniform_buffer<T: Pod>(&self, value: &T) -> Result<PooledBuffer, GpuError> {
+        let contents = bytemuck::bytes_of(value);
+        let buffer = self.acquire_buffer(
+            contents.len() as u64,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        )?;
+        self.queue.write_buffer(&buffer, 0, contents);
+        Ok(buffer)
+    }
 
-            result
+    /// workgroups covering `invocations`, folded into y and then z past the per dimension limit,
+    /// kernels rebuild the index as `gid.x + (gid.y + gid.z * nwg.y) * nwg.x * workgroup_size`,
+    /// z only comes in below 2 invocations per workgroup so kernels of 64 and up may ignore it
+    pub fn grid_1d(&self, invocations: u32, workgroup_size: u32) -> (u32, u32, u32) {
+        let groups = invocations.div_ceil(workgroup_size);
+        let max = self.device.limits().max_compute_workgroups_per_dimension;
+        if groups <= max {
+            return (groups, 1, 1);
         }
-    };
-}
+        let rows = groups.div_ceil(max);
+        if rows <= max {
+            (max, rows, 1)
+        } else {
+            (max, max, rows.div_ceil(max))
+        }
+    }
 
+    /// bytes the tiling lets one storage binding hold, in deterministic mode the webgpu default
+    /// so every device splits (and accumulates) the same way
+    pub(crate) fn binding_budget(&self) -> u64 {
+        let limits = self.device.limits();
+        let binding = if self.is_deterministic() {
+            wgpu::Limits::default().max_storage_buffer_binding_size
+        } else {
+            limits.max_storage_buffer_binding_size
+        };
+        (binding as u64).min(limits.max_buffer_size)
+    }
 
-#[repr(C)]
-#[derive(Copy, Clone, Pod, Zeroable, Debug)]
-struct SingleU32 {
-    value: u32,
-}
+    /// values of `elem_size` bytes per chunk of a split elementwise op, chunk offsets stay
+    /// aligned for binding at any of them
+    pub(crate) fn binding_chunk(&self, elem_size: u64) -> u64 {
+        let align = self.device.limits().min_storage_buffer_offset_alignment as u64;
+        let chunk = self.binding_budget() / elem_size;
+        (chunk - chunk % align).max(align)
+    }
 
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable, Debug)]
-pub struct MatrixHeader {
-    long: u32,
-    width: u32,
-}
+    /// submit a single dispatch without reading anything back
+    pub async fn dispatch(
+        &self,
+        call_func: &str,
+        group: u32,
+        workgroups: (u32, u32, u32),
+        entries: &[wgpu::BindGroupEntry<'_>],
+    ) -> Result<(), GpuError> {
+        self.dispatch_groups(call_func, workgroups, &[(group, entries)]).await
+    }
 
+    /// submit a single dispatch binding several groups
+    pub async fn dispatch_groups(
+        &self,
+        call_func: &str,
+        workgroups: (u32, u32, u32),
+        groups: &[(u32, &[wgpu::BindGroupEntry<'_>])],
+    ) -> Result<(), GpuError> {
+        self.scoped(|| {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.encode_dispatch_groups(&mut encoder, call_func, workgroups, groups)?;
+            self.queue.submit(Some(encoder.finish()));
+            Ok(())
+        })
+        .await
+    }
 
-impl GpuManager {
-    pub async fn run_add_u32(&self, a: u32, b: u32) -> Result<u32, String> {
-        let a_data = SingleU32 { value: a };
-        let b_data = SingleU32 { value: b };
+    /// map the requested range of a MAP_READ pool buffer, resolves once the gpu is done with it
+    pub async fn map_read(&self, buffer: &PooledBuffer) -> Result<(), GpuError> {
+        let start = now_ms();
+        let (sender, receiver) = oneshot_channel();
+        // cleared only once the callback ran, a future dropped before that leaves it set
+        buffer.set_map_pending(true);
+        buffer
+            .slice(..buffer.byte_len())
+            .map_async(wgpu::MapMode::Read, move |v| {
+                let _ = sender.send(v);
+            });
 
-        let buffer_a = define_input_struct!(self, a_data);
+        let mapped = receiver.receive().await;
+        buffer.set_map_pending(false);
+        self.record_readback(start);
+        match mapped {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) => {
+                // a lost device is the more useful report
+                self.check_device()?;
+                Err(GpuError::MapFailed(e.to_string()))
+            }
+            None => Err(GpuError::MapFailed("map callback dropped".into())),
+        }
+    }
 
-        let buffer_b = define_input_struct!(self, b_data);
+    /// copy the first `bytes` bytes of `buffer` to the cpu
+    pub async fn read_back_bytes(&self, buffer: &wgpu::Buffer, bytes: u64) -> Result<Vec<u8>, GpuError> {
+        // copies move whole words, f16 and u8 tensors can end mid word
+        let result_readback = self.acquire_buffer(
+            bytes.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        )?;
+
+        self.scoped(|| {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            encoder.copy_buffer_to_buffer(buffer, 0, &result_readback, 0, result_readback.byte_len());
+            self.queue.submit(Some(encoder.finish()));
+            Ok(())
+        })
+        .await?;
 
-        let buffer_result = define_output_struct!(self, SingleU32);
+        self.map_read(&result_readback).await?;
 
-        let result: u32 = run_gpu_result_struct!(self, "add_u32", 0, SingleU32, 1, 1, 1, buffer_result,
-            0 => buffer_a,
-            1 => buffer_b,
-            2 => buffer_result,
-        );
+        let slice = result_readback.slice(..result_readback.byte_len());
+        let data = slice.get_mapped_range();
+        let mut result = data.to_vec();
+        result.truncate(bytes as usize);
+
+        drop(data);
+        result_readback.unmap();
 
         Ok(result)
     }
@@ -240,21 +297,35 @@ impl GpuManager {
         &self,
         input_a: &[f32],
         input_b: &[f32],
-    ) -> Result<Vec<f32>, String> {
+    ) -> Result<Vec<f32>, GpuError> {
 
         let len = input_a.len();
         if input_b.len() != len {
-            return Err("Input length mismatch".into());
+            return Err(GpuError::ShapeMismatch(format!("input lengths {} vs {}", len, input_b.len())));
+        }
+
+        // arrays over the binding limit go through in chunks that fit
+        let chunk = self.binding_chunk(4) as usize;
+        let mut result = Vec::with_capacity(len);
+        for (a, b) in input_a.chunks(chunk).zip(input_b.chunks(chunk)) {
+            result.extend(self.add_chunk(a, b).await?);
         }
-        
+        Ok(result)
+    }
+
+    async fn add_chunk(&self, input_a: &[f32], input_b: &[f32]) -> Result<Vec<f32>, GpuError> {
+        let len = input_a.len();
+        let params = self.uniform_buffer(&ElementwiseParams::new(len as u32, 0.0, 0.0))?;
         let buffer_a = define_input_array!(self, input_a);
         let buffer_b = define_input_array!(self, input_b);
-        let buffer_result = define_output_array!(self, len);
-        
-        let result = run_gpu_result_array!(self, "add", 0, len, len as u32, 1, 1, buffer_result,
-            3 => buffer_a,
-            4 => buffer_b,
-            5 => buffer_result,
+        let buffer_result = define_output_array!(self, len, f32);
+        let (x, y, z) = self.grid_1d(len as u32, ELEMENTWISE_WORKGROUP_SIZE);
+
+        let result = run_gpu_result_array!(self, "add", 0, len, f32, x, y, z, buffer_result,
+            0 => params,
+            1 => buffer_a,
+            2 => buffer_b,
+            4 => buffer_result,
         );
         Ok(result)
     }
@@ -263,41 +334,27 @@ impl GpuManager {
         &self,
         a: Vec<Vec<f32>>,
         b: Vec<Vec<f32>>,
-    ) -> Result<Vec<Vec<f32>>, String> {
+    ) -> Result<Vec<Vec<f32>>, GpuError> {
         let a_long = a.len() as u32;
         let a_width = a.first().map_or(0, |r| r.len() as u32);
         let b_long = b.len() as u32;
         let b_width = b.first().map_or(0, |r| r.len() as u32);
 
-        assert_eq!(
-            a_width, b_long,
-            "Matrix A's width must equal Matrix B's height."
-        );
+        if a_width != b_long {
+            return Err(GpuError::ShapeMismatch("Matrix A's width must equal Matrix B's height.".into()));
+        }
+        if a.iter().any(|r| r.len() as u32 != a_width) || b.iter().any(|r| r.len() as u32 != b_width) {
+            return Err(GpuError::ShapeMismatch("matrix rows must all have the same length".into()));
+        }
 
-        let out_long = a_long;
-        let out_width = b_width;
-        let out_size = out_long * out_width;
         let flatten = |m: Vec<Vec<f32>>| m.into_iter().flatten().collect::<Vec<_>>();
         let a_data = flatten(a);
         let b_data = flatten(b);
 
-        let a_matrix_info = define_input_struct!(self, MatrixHeader { long: a_long, width:a_width});
-        let b_matrix_info = define_input_struct!(self, MatrixHeader { long: b_long, width:b_width});
-        let a_matrix = define_input_array!(self, a_data);
-        let b_matrix = define_input_array!(self, b_data);
-        
-        let buffer_result = define_output_array!(self, out_size);
-        
-        let result = run_gpu_result_array!(self, "matrix_multiply", 0, out_size, (out_width + 15) / 16, (out_long + 15) / 16, 1, buffer_result,
-            6 => a_matrix_info,
-            7 => b_matrix_info,
-            8 => a_matrix,
-            9 => b_matrix,
-            10 =>buffer_result
-        );
-        
+        let result = self.gemm(&GemmSpec::new(a_long, b_width, a_width), &a_data, &b_data, None, None).await?;
+
         let matrix = result
-            .chunks(out_width as usize)
+            .chunks(b_width.max(1) as usize)
             .map(|row| row.to_vec())
             .collect::<Vec<_>>();
 
@@ -308,29 +365,39 @@ impl GpuManager {
         &self,
         a: Vec<f32>,
         b: Vec<Vec<f32>>,
-    ) -> Result<Vec<f32>, String> {
+    ) -> Result<Vec<f32>, GpuError> {
 
         let b_long = b.len() as u32;
         let b_width = b.first().map_or(0, |r| r.len() as u32);
 
-        assert_eq!(a.len() as u32, b_long, "Vector A's width must equal Matrix B's height.");
+        if a.len() as u32 != b_long {
+            return Err(GpuError::ShapeMismatch("Vector A's width must equal Matrix B's height.".into()));
+        }
+        if b.iter().any(|r| r.len() as u32 != b_width) {
+            return Err(GpuError::ShapeMismatch("matrix rows must all have the same length".into()));
+        }
 
         let flatten = |m: Vec<Vec<f32>>| m.into_iter().flatten().collect::<Vec<_>>();
         let b_data = flatten(b);
 
+        // a matrix too large to bind is a single row gemm, which tiles
+        if b_data.len() as u64 * 4 > self.binding_budget() {
+            return self.gemm(&GemmSpec::new(1, b_width, b_long), &a, &b_data, None, None).await;
+        }
+
         let vector_buffer = define_input_array!(self, a);
         let matrix_header = define_input_struct!(self, MatrixHeader { long: b_long, width: b_width });
         let matrix_buffer = define_input_array!(self, b_data);
-        let result_buffer = define_output_array!(self, b_width);
+        let result_buffer = define_output_array!(self, b_width, f32);
 
-        let workgroup_count_x = (b_width + 63) / 64;
+        let (x, y, z) = self.grid_1d(b_width, 64);
 
-        let result = run_gpu_result_array!(self, "vector_matrix_multiply", 0, b_width, workgroup_count_x, 1, 1,
+        let result = run_gpu_result_array!(self, "vector_matrix_multiply", 0, b_width, f32, x, y, z,
             result_buffer,
-            11 => vector_buffer,
-            12 => matrix_header,
-            13 => matrix_buffer,
-            14 => result_buffer
+            0 => vector_buffer,
+            1 => matrix_header,
+            2 => matrix_buffer,
+            3 => result_buffer
         );
 
         Ok(result)
@@ -0,0 +1,403 @@
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::element_count;
+
+/// sgemm over flat row major buffers: `C = alpha * op(A) * op(B) + beta * C + bias` for each
+/// matrix of a batch, op transposes when the flag is set, bias holds one value per column
+#[derive(Debug, Clone, Copy)]
+pub struct GemmSpec {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+    pub batch: u32,
+    pub trans_a: bool,
+    pub trans_b: bool,
+    /// distance between rows as stored, 0 means packed
+    pub lda: u32,
+    pub ldb: u32,
+    pub ldc: u32,
+    /// distance between the matrices of a batch, 0 means packed
+    pub stride_a: u32,
+    pub stride_b: u32,
+    pub stride_c: u32,
+    pub alpha: f32,
+    pub beta: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GemmParams {
+    m: u32,
+    n: u32,
+    k: u32,
+    batch: u32,
+    lda: u32,
+    ldb: u32,
+    ldc: u32,
+    trans_a: u32,
+    stride_a: u32,
+    stride_b: u32,
+    stride_c: u32,
+    trans_b: u32,
+    alpha: f32,
+    beta: f32,
+    has_c: u32,
+    has_bias: u32,
+}
+
+/// values a strided batch reaches, the last row only needs `cols`
+fn extent(batch: u32, rows: u32, cols: u32, ld: u32, stride: u32) -> u64 {
+    if batch == 0 || rows == 0 || cols == 0 {
+        return 0;
+    }
+    (batch - 1) as u64 * stride as u64 + (rows - 1) as u64 * ld as u64 + cols as u64
+}
+
+impl GemmSpec {
+    /// plain `m x k` times `k x n`
+    pub fn new(m: u32, n: u32, k: u32) -> Self {
+        Self {
+            m,
+            n,
+            k,
+            batch: 1,
+            trans_a: false,
+            trans_b: false,
+            lda: 0,
+            ldb: 0,
+            ldc: 0,
+            stride_a: 0,
+            stride_b: 0,
+            stride_c: 0,
+            alpha: 1.0,
+            beta: 0.0,
+        }
+    }
+
+    /// stored (rows, cols) of A and B
+    fn a_dims(&self) -> (u32, u32) {
+        if self.trans_a { (self.k, self.m) } else { (self.m, self.k) }
+    }
+
+    fn b_dims(&self) -> (u32, u32) {
+        if self.trans_b { (self.n, self.k) } else { (self.k, self.n) }
+    }
+
+    /// zero leading dims and strides replaced by the packed ones
+    pub fn resolved(&self) -> Self {
+        let mut spec = *self;
+        let (a_rows, a_cols) = spec.a_dims();
+        let (b_rows, b_cols) = spec.b_dims();
+        if spec.lda == 0 {
+            spec.lda = a_cols;
+        }
+        if spec.ldb == 0 {
+            spec.ldb = b_cols;
+        }
+        if spec.ldc == 0 {
+            spec.ldc = spec.n;
+        }
+        if spec.stride_a == 0 {
+            spec.stride_a = a_rows * spec.lda;
+        }
+        if spec.stride_b == 0 {
+            spec.stride_b = b_rows * spec.ldb;
+        }
+        if spec.stride_c == 0 {
+            spec.stride_c = spec.m * spec.ldc;
+        }
+        spec
+    }
+
+    /// check a resolved spec against the buffer lengths, returns the length of the output
+    pub fn check(&self, a_len: u64, b_len: u64, c_len: Option<u64>, bias_len: Option<u64>) -> Result<u64, GpuError> {
+        let (a_rows, a_cols) = self.a_dims();
+        let (b_rows, b_cols) = self.b_dims();
+        if self.lda < a_cols || self.ldb < b_cols || self.ldc < self.n {
+            return Err(GpuError::InvalidArgument("gemm leading dimension is shorter than a row".into()));
+        }
+
+        let c_extent = extent(self.batch, self.m, self.n, self.ldc, self.stride_c);
+        if self.batch > 1 && (self.stride_c as u64) < extent(1, self.m, self.n, self.ldc, 0) {
+            return Err(GpuError::InvalidArgument("gemm output matrices of a batch overlap".into()));
+        }
+
+        for (name, len, needed) in [
+            ("a", a_len, extent(self.batch, a_rows, a_cols, self.lda, self.stride_a)),
+            ("b", b_len, extent(self.batch, b_rows, b_cols, self.ldb, self.stride_b)),
+            ("c", c_len.unwrap_or(c_extent), c_extent),
+        ] {
+            if len < needed {
+                return Err(GpuError::ShapeMismatch(format!("gemm {} holds {} values, needs {}", name, len, needed)));
+            }
+        }
+        if let Some(len) = bias_len {
+            if len != self.n as u64 {
+                return Err(GpuError::ShapeMismatch(format!("gemm bias holds {} values, needs {}", len, self.n)));
+            }
+        }
+
+        Ok(c_len.unwrap_or(c_extent))
+    }
+
+    /// shape of a fresh output, matrices when packed and flat otherwise
+    pub(crate) fn out_shape(&self, len: u64) -> Vec<u32> {
+        if self.ldc != self.n || self.stride_c != self.m * self.n {
+            vec![len as u32]
+        } else if self.batch == 1 {
+            vec![self.m, self.n]
+        } else {
+            vec![self.batch, self.m, self.n]
+        }
+    }
+
+    fn params(&self, has_c: bool, has_bias: bool) -> GemmParams {
+        GemmParams {
+            m: self.m,
+            n: self.n,
+            k: self.k,
+            batch: self.batch,
+            lda: self.lda,
+            ldb: self.ldb,
+            ldc: self.ldc,
+            trans_a: self.trans_a as u32,
+            stride_a: self.stride_a,
+            stride_b: self.stride_b,
+            stride_c: self.stride_c,
+            trans_b: self.trans_b as u32,
+            alpha: self.alpha,
+            beta: self.beta,
+            has_c: has_c as u32,
+            has_bias: has_bias as u32,
+        }
+    }
+}
+
+fn tensor_len(shape: &[u32]) -> Result<u64, GpuError> {
+    element_count(shape).map(u64::from)
+}
+
+/// (rows, cols, depth) of the output tiles a split gemm runs
+type Tiles = (u32, u32, u32);
+
+impl GpuManager {
+    /// largest gemm extent along the dispatched x and y, the kernel covers 16 per workgroup
+    fn gemm_max_dim(&self) -> u32 {
+        let groups = if self.is_deterministic() {
+            wgpu::Limits::default().max_compute_workgroups_per_dimension
+        } else {
+            self.device.limits().max_compute_workgroups_per_dimension
+        };
+        groups.saturating_mul(16)
+    }
+
+    /// whether a resolved spec runs as one dispatch, otherwise the tile sizes that fit
+    fn gemm_tiles(&self, spec: &GemmSpec, lens: [u64; 3]) -> Option<Tiles> {
+        let budget = (self.binding_budget() / 4).min(u32::MAX as u64) as u32;
+        let max_dim = self.gemm_max_dim();
+        let max_batch = self.device.limits().max_compute_workgroups_per_dimension;
+        if lens.iter().all(|len| *len <= budget as u64) && spec.m <= max_dim && spec.n <= max_dim && spec.batch <= max_batch {
+            return None;
+        }
+
+        // a row of A and a column of B are split along k only when they alone are over the budget
+        let depth = spec.k.clamp(1, budget);
+        let cols = spec.n.min(budget / depth).min(max_dim).max(1);
+        let rows = spec.m.min(budget / depth.max(cols)).min(max_dim).max(1);
+        Some((rows, cols, depth))
+    }
+
+    /// a gemm too large to bind, stitched from packed tiles on the host, tiles along k
+    /// accumulate onto the previous partial so only the first one sees `c` and the bias
+    async fn gemm_tiled(
+        &self,
+        spec: &GemmSpec,
+        (a, b): (&[f32], &[f32]),
+        c: Option<&[f32]>,
+        bias: Option<&[f32]>,
+        (tile_m, tile_n, tile_k): Tiles,
+        len: usize,
+    ) -> Result<Vec<f32>, GpuError> {
+        let mut out = c.map_or_else(|| vec![0.0; len], |c| c.to_vec());
+        let (lda, ldb, ldc) = (spec.lda as usize, spec.ldb as usize, spec.ldc as usize);
+
+        for batch in 0..spec.batch as usize {
+            let base_a = batch * spec.stride_a as usize;
+            let base_b = batch * spec.stride_b as usize;
+            let base_c = batch * spec.stride_c as usize;
+            let a_at = |r: usize, d: usize| if spec.trans_a { a[base_a + d * lda + r] } else { a[base_a + r * lda + d] };
+            let b_at = |d: usize, j: usize| if spec.trans_b { b[base_b + j * ldb + d] } else { b[base_b + d * ldb + j] };
+
+            for i0 in (0..spec.m).step_by(tile_m as usize) {
+                let rows = tile_m.min(spec.m - i0);
+                for j0 in (0..spec.n).step_by(tile_n as usize) {
+                    let cols = tile_n.min(spec.n - j0);
+                    let cells = |r: u32, j: u32| base_c + (i0 + r) as usize * ldc + (j0 + j) as usize;
+
+                    let mut partial = c.map(|_| {
+                        (0..rows).flat_map(|r| (0..cols).map(move |j| (r, j))).map(|(r, j)| out[cells(r, j)]).collect::<Vec<f32>>()
+                    });
+                    let mut beta = spec.beta;
+                    // k = 0 still runs once for beta * c + bias
+                    for k0 in (0..spec.k.max(1)).step_by(tile_k as usize) {
+                        let depth = tile_k.min(spec.k.saturating_sub(k0));
+                        let a_tile: Vec<f32> = (0..rows as usize)
+                            .flat_map(|r| (0..depth as usize).map(move |d| (r, d)))
+                            .map(|(r, d)| a_at(i0 as usize + r, k0 as usize + d))
+                            .collect();
+                        let b_tile: Vec<f32> = (0..depth as usize)
+                            .flat_map(|d| (0..cols as usize).map(move |j| (d, j)))
+                            .map(|(d, j)| b_at(k0 as usize + d, j0 as usize + j))
+                            .collect();
+                        let bias_tile = bias.filter(|_| k0 == 0).map(|bias| &bias[j0 as usize..(j0 + cols) as usize]);
+
+                        let mut tile = GemmSpec::new(rows, cols, depth);
+                        tile.alpha = spec.alpha;
+                        tile.beta = beta;
+                        partial = Some(self.gemm_once(&tile, &a_tile, &b_tile, partial.as_deref(), bias_tile).await?);
+                        beta = 1.0;
+                    }
+
+                    if let Some(partial) = partial {
+                        for (cell, value) in (0..rows).flat_map(|r| (0..cols).map(move |j| (r, j))).zip(partial) {
+                            out[cells(cell.0, cell.1)] = value;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// gemm on resident f32 tensors, the result is a new tensor shaped like `c` when given,
+    /// operands too large to bind are tiled from host copies
+    pub async fn tensor_gemm(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError> {
+        let spec = spec.resolved();
+        let shape_a = self.tensor(a, DType::F32)?.1;
+        let shape_b = self.tensor(b, DType::F32)?.1;
+        let shape_c = c.map(|id| self.tensor(id, DType::F32)).transpose()?.map(|(_, shape)| shape);
+        let bias_len = bias.map(|id| self.tensor(id, DType::F32)).transpose()?.map(|(_, shape)| tensor_len(&shape)).transpose()?;
+        let (len_a, len_b) = (tensor_len(&shape_a)?, tensor_len(&shape_b)?);
+
+        let len = spec.check(len_a, len_b, shape_c.as_deref().map(tensor_len).transpose()?, bias_len)?;
+        let Some(tiles) = self.gemm_tiles(&spec, [len_a, len_b, len]) else {
+            return self.tensor_gemm_once(&spec, a, b, c, bias).await;
+        };
+
+        let host_a = self.tensor_download(a).await?;
+        let host_b = self.tensor_download(b).await?;
+        let mut host_c = None;
+        if let Some(id) = c {
+            host_c = Some(self.tensor_download(id).await?);
+        }
+        let mut host_bias = None;
+        if let Some(id) = bias {
+            host_bias = Some(self.tensor_download(id).await?);
+        }
+
+        let out = self
+            .gemm_tiled(&spec, (&host_a, &host_b), host_c.as_deref(), host_bias.as_deref(), tiles, len as usize)
+            .await?;
+        self.tensor_upload(&out, shape_c.unwrap_or_else(|| spec.out_shape(len)))
+    }
+
+    /// one dispatch, the operands must fit the device limits
+    async fn tensor_gemm_once(&self, spec: &GemmSpec, a: u64, b: u64, c: Option<u64>, bias: Option<u64>) -> Result<u64, GpuError> {
+        let spec = spec.resolved();
+        let (buffer_a, shape_a) = self.tensor(a, DType::F32)?;
+        let (buffer_b, shape_b) = self.tensor(b, DType::F32)?;
+        let c = c.map(|id| self.tensor(id, DType::F32)).transpose()?;
+        let bias = bias.map(|id| self.tensor(id, DType::F32)).transpose()?;
+
+        let len = spec.check(
+            tensor_len(&shape_a)?,
+            tensor_len(&shape_b)?,
+            c.as_ref().map(|(_, shape)| tensor_len(shape)).transpose()?,
+            bias.as_ref().map(|(_, shape)| tensor_len(shape)).transpose()?,
+        )?;
+
+        let out_shape = match &c {
+            Some((_, shape)) => shape.clone(),
+            None => spec.out_shape(len),
+        };
+        let params = self.uniform_buffer(&spec.params(c.is_some(), bias.is_some()))?;
+        let result = self.alloc_tensor(&out_shape, DType::F32)?;
+
+        // absent operands are never read, `a` stands in for their bindings
+        let c_buffer = c.as_ref().map_or(&buffer_a, |(buffer, _)| buffer);
+        let bias_buffer = bias.as_ref().map_or(&buffer_a, |(buffer, _)| buffer);
+
+        self.scoped(|| {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            // the kernel only writes the m x n cells, everything between strided rows keeps c
+            if c.is_some() {
+                encoder.copy_buffer_to_buffer(c_buffer, 0, &result, 0, len * 4);
+            } else {
+                encoder.clear_buffer(&result, 0, None);
+            }
+
+            self.encode_dispatch(&mut encoder, "gemm", 0, (spec.n.div_ceil(16), spec.m.div_ceil(16), spec.batch), &[
+                wgpu::BindGroupEntry { binding: 0, resource: params.binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: buffer_a.binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: buffer_b.binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: c_buffer.binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: bias_buffer.binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: result.binding() },
+            ])?;
+            self.queue.submit(Some(encoder.finish()));
+            Ok(())
+        })
+        .await?;
+
+        Ok(self.tensors.lock().unwrap().insert(result, out_shape, DType::F32))
+    }
+
+    /// gemm on host buffers, returns the whole output buffer (a copy of `c` with the products written in),
+    /// split into tiles when an operand or the grid is over the device limits
+    pub async fn gemm(
+        &self,
+        spec: &GemmSpec,
+        a: &[f32],
+        b: &[f32],
+        c: Option<&[f32]>,
+        bias: Option<&[f32]>,
+    ) -> Result<Vec<f32>, GpuError> {
+        let resolved = spec.resolved();
+        let len = resolved.check(a.len() as u64, b.len() as u64, c.map(|c| c.len() as u64), bias.map(|b| b.len() as u64))?;
+        match self.gemm_tiles(&resolved, [a.len() as u64, b.len() as u64, len]) {
+            None => self.gemm_once(spec, a, b, c, bias).await,
+            Some(tiles) => self.gemm_tiled(&resolved, (a, b), c, bias, tiles, len as usize).await,
+        }
+    }
+
+    /// one dispatch through temporary tensors
+    async fn gemm_once(
+        &self,
+        spec: &GemmSpec,
+        a: &[f32],
+        b: &[f32],
+        c: Option<&[f32]>,
+        bias: Option<&[f32]>,
+    ) -> Result<Vec<f32>, GpuError> {
+        let upload = |data: &[f32]| self.tensor_upload(data, vec![data.len() as u32]);
+        let mut ids = vec![upload(a)?, upload(b)?];
+        let c = c.map(&upload).transpose()?;
+        let bias = bias.map(&upload).transpose()?;
+        ids.extend(c);
+        ids.extend(bias);
+
+        let result = match self.tensor_gemm_once(spec, ids[0], ids[1], c, bias).await {
+            Ok(id) => {
+                ids.push(id);
+                self.tensor_download(id).await
+            }
+            Err(e) => Err(e),
+        };
+
+        for id in ids {
+            let _ = self.tensor_free(id);
+        }
+        result
+    }
+}
@@ -1,107 +1,300 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::{gpu_shade::SHADER_SOURCE};
+use crate::gpu_shade::{
+    conv_source, nn_source, sized_source, typed_source, CONV_KERNELS, KERNELS, NN_KERNELS, SIZED_KERNELS, TYPED_KERNELS,
+};
+use crate::gpu_dtype::DType;
+use crate::gpu_reflect::create_reflected_pipeline;
+use crate::gpu_buffer_pool::{BufferPool, PoolStats, PooledBuffer};
+use crate::gpu_tensor::TensorStore;
+use crate::gpu_batch::BatchStore;
+use crate::gpu_kernel::KernelSource;
+use crate::gpu_adapter::AdapterPolicy;
+use crate::gpu_error::GpuError;
+use crate::gpu_profile::Profiler;
+use crate::gpu_pipeline_cache::PipelineCache;
 
 #[derive(Clone)]
 pub struct GpuManager {
+    pub adapter_info: wgpu::AdapterInfo,
+    /// kept to ask for a new device once this one is lost
+    adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub pipelines: HashMap<String, wgpu::ComputePipeline>,
+    /// compiled pipelines by name, built-in kernels are only added when first used
+    pub pipelines: Arc<RwLock<HashMap<String, wgpu::ComputePipeline>>>,
+    pub(crate) pipeline_cache: Arc<PipelineCache>,
+    /// kernels loaded at run time by entry point, kept to rebuild them on a new device
+    pub kernels: Arc<RwLock<HashMap<String, KernelSource>>>,
+    pub pool: Arc<Mutex<BufferPool>>,
+    pub tensors: Arc<Mutex<TensorStore>>,
+    pub batches: Arc<Mutex<BatchStore>>,
+    /// when set, kernels must accumulate in an order that does not depend on the device,
+    /// so two honest workers produce bit identical output
+    deterministic: Arc<AtomicBool>,
+    /// set by the device lost callback
+    lost: Arc<Mutex<Option<String>>>,
+    pub(crate) profiler: Arc<Mutex<Profiler>>,
 }
 
-macro_rules! define_pipeline {
-    (
-        $name:expr,
-        $device_:expr,
-        $shader_module_: expr,
-        bindings = [$($binding:expr),+ $(,)?]
-    ) => {{
-
-        let mut entries = Vec::new();
-        let bindings = vec![$($binding),+];
-        let last = bindings.len() - 1;
-
-        for (i, binding) in bindings.iter().enumerate() {
-            let read_only = i != last;
-            entries.push(wgpu::BindGroupLayoutEntry {
-                binding: *binding,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            });
-        }
-
-        let bind_group_layout = $device_.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some(&format!("{}_bgl", $name)),
-            entries: &entries,
-        });
-
-        let pipeline_layout = $device_.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some(&format!("{}_layout", $name)),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        $device_.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some($name),
-            layout: Some(&pipeline_layout),
-            module: $shader_module_,
-            entry_point: Some($name),
-            cache: None,
-            compilation_options: Default::default(),
-        })
-    }};
+/// source of the built-in kernel `name`, typed kernels in their f32 form
+fn builtin_source(name: &str) -> Option<String> {
+    let find = |kernels: &[(&str, &'static str)]| kernels.iter().find(|(kernel, _)| *kernel == name).map(|(_, source)| *source);
+    find(KERNELS)
+        .map(str::to_string)
+        .or_else(|| find(TYPED_KERNELS).map(|template| typed_source(template, "f32")))
+        .or_else(|| find(NN_KERNELS).map(nn_source))
+        .or_else(|| find(CONV_KERNELS).map(conv_source))
 }
 
+/// whether `name` is, or may later be compiled as, the pipeline of a built-in kernel,
+/// typed and sized kernels are keyed `<base>_<dtype>` and `<base>_<workgroup size>`
+pub(crate) fn is_builtin_name(name: &str) -> bool {
+    let variant_of = |base: &str| name.strip_prefix(base).is_some_and(|rest| rest.starts_with('_'));
+    builtin_source(name).is_some()
+        || TYPED_KERNELS.iter().any(|(base, _)| variant_of(base))
+        || SIZED_KERNELS.iter().any(|(base, _)| *base == name || variant_of(base))
+}
 
 impl GpuManager {
-    /// async init, get adapter,device,queue and load shader, crate mult pipeline
-    pub async fn new() -> Self {
-        let instance = wgpu::Instance::default();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await
-            .expect("Failed to find an appropriate adapter");
+    /// one manager per adapter `policy` selects, devices that fail to open are skipped
+    pub async fn open(policy: &AdapterPolicy) -> Result<Vec<Self>, GpuError> {
+        let instance = policy.instance();
+        let mut managers = Vec::new();
+        let mut last_error = GpuError::NoAdapter;
+        for adapter in policy.select(&instance).await? {
+            match Self::new(&adapter, policy).await {
+                Ok(manager) => managers.push(manager),
+                Err(e) => {
+                    log::warn!("opening {} failed: {}", adapter.get_info().name, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        if managers.is_empty() {
+            return Err(last_error);
+        }
+        Ok(managers)
+    }
+
+    /// async init, get device,queue of `adapter` and open its pipeline cache,
+    /// pipelines are compiled when first used
+    pub async fn new(adapter: &wgpu::Adapter, policy: &AdapterPolicy) -> Result<Self, GpuError> {
+        // take the optional features we can make use of and the adapter's real limits,
+        // both end up in the capability report
+        let wanted_features = wgpu::Features::SHADER_F16 | wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::SUBGROUP
+            | wgpu::Features::PIPELINE_CACHE;
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
+                required_features: adapter.features() & wanted_features,
+                required_limits: adapter.limits(),
                 memory_hints: wgpu::MemoryHints::default(),
                 trace: wgpu::Trace::Off,
             })
             .await
-            .expect("Failed to create device");
+            .map_err(|e| GpuError::RequestDevice(e.to_string()))?;
+
+        let lost = Arc::new(Mutex::new(None));
+        let lost_flag = lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            log::error!("gpu device lost ({:?}): {}", reason, message);
+            *lost_flag.lock().unwrap() = Some(format!("{:?}: {}", reason, message));
+        });
+
+        // wgpu panics on errors outside of an error scope by default
+        device.on_uncaptured_error(Box::new(|e| {
+            log::error!("uncaptured gpu error: {}", e);
+        }));
+
+        let pipeline_cache = PipelineCache::open(&device, &adapter.get_info(), policy.pipeline_cache_dir.as_deref());
+        let profiler = Profiler::new(&device);
+        let mut pool = BufferPool::new(&device.limits());
+        if let Some(cap_mb) = policy.pool_cap_mb {
+            pool.set_cap(cap_mb << 20);
+        }
+        Ok(GpuManager {
+            adapter_info: adapter.get_info(),
+            adapter: adapter.clone(),
+            device,
+            queue,
+            pipelines: Arc::new(RwLock::new(HashMap::new())),
+            pipeline_cache: Arc::new(pipeline_cache),
+            kernels: Arc::new(RwLock::new(HashMap::new())),
+            pool: Arc::new(Mutex::new(pool)),
+            tensors: Arc::new(Mutex::new(TensorStore::new())),
+            batches: Arc::new(Mutex::new(BatchStore::new())),
+            deterministic: Arc::new(AtomicBool::new(false)),
+            lost,
+            profiler: Arc::new(Mutex::new(profiler)),
+        })
+    }
+
+    /// fails once the device is lost, checked before touching the device
+    pub fn check_device(&self) -> Result<(), GpuError> {
+        match self.lost_reason() {
+            Some(reason) => Err(GpuError::DeviceLost(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// why the device was lost, `None` while it is healthy
+    pub fn lost_reason(&self) -> Option<String> {
+        self.lost.lock().unwrap().clone()
+    }
 
-        let shader_source_ = SHADER_SOURCE;
+    /// a manager with a new device in place of a lost one, runtime kernels that still
+    /// compile, the deterministic flag and the pool cap carry over, resident tensors and batches are gone and their
+    /// handles stay invalid since the stores keep counting
+    pub async fn recover(&self, policy: &AdapterPolicy) -> Result<Self, GpuError> {
+        self.release_buffers();
+        let fresh = match Self::new(&self.adapter, policy).await {
+            Ok(fresh) => fresh,
+            // a browser adapter hands out a single device, a new one has to be requested
+            Err(e) => {
+                log::warn!("reopening {} failed: {}, selecting again", self.adapter_info.name, e);
+                let instance = policy.instance();
+                let adapters = policy.select(&instance).await?;
+                let adapter = match adapters.iter().find(|adapter| adapter.get_info().name == self.adapter_info.name) {
+                    Some(adapter) => adapter,
+                    None => adapters.first().ok_or(GpuError::NoAdapter)?,
+                };
+                Self::new(adapter, policy).await?
+            }
+        };
+        let manager = GpuManager { tensors: self.tensors.clone(), batches: self.batches.clone(), ..fresh };
+        manager.set_deterministic(self.is_deterministic());
+        manager.set_pool_cap(self.pool_stats().cap_bytes);
 
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("MultiComputeShader"),
-            source: wgpu::ShaderSource::Wgsl(shader_source_.into()),
+        let kernels: Vec<KernelSource> = self.kernels.read().unwrap().values().cloned().collect();
+        for kernel in kernels.iter() {
+            // the new device may lack a feature the kernel needs, the rest still work
+            if let Err(e) = manager.load_kernel(kernel).await {
+                log::error!("reloading kernel {} on {} failed: {}", kernel.name, manager.adapter_info.name, e);
+            }
+        }
+        manager.pipeline_cache.save();
+        Ok(manager)
+    }
+
+    /// the pipeline `name`, built-in kernels are compiled on first use
+    pub fn pipeline(&self, name: &str) -> Result<wgpu::ComputePipeline, GpuError> {
+        self.check_device()?;
+        if let Some(pipeline) = self.pipelines.read().unwrap().get(name) {
+            return Ok(pipeline.clone());
+        }
+
+        let source = builtin_source(name).ok_or_else(|| GpuError::UnknownPipeline(name.to_string()))?;
+        let pipeline = self.compile_builtin(name, &source)?;
+        self.pipelines.write().unwrap().insert(name.to_string(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// compile a built-in kernel through the pipeline cache, `entry_point` is also its label
+    fn compile_builtin(&self, entry_point: &str, source: &str) -> Result<wgpu::ComputePipeline, GpuError> {
+        let shader_module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(entry_point),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         });
 
-        let add_t_pipeline = define_pipeline!("add_u32",&device, &shader_module,bindings = [0, 1, 2]);
-        let add = define_pipeline!("add",&device, &shader_module,bindings = [3, 4, 5]);
-        let matrix_mult = define_pipeline!("matrix_multiply",&device, &shader_module,bindings = [6, 7, 8, 9, 10]);
-        let vector_matrix_multiply = define_pipeline!("vector_matrix_multiply",&device, &shader_module, bindings = [11, 12, 13, 14]);
+        let pipeline = create_reflected_pipeline(&self.device, &shader_module, source, entry_point, self.pipeline_cache.get())
+            .map_err(|e| GpuError::Shader(format!("built-in kernel {}: {}", entry_point, e)))?;
+        Ok(pipeline)
+    }
+
+    /// pipeline name of the built-in `base` kernel for `dtype`, compiled on first use
+    pub fn typed_kernel(&self, base: &str, dtype: DType) -> Result<String, GpuError> {
+        let name = dtype.kernel_name(base);
+        if self.pipelines.read().unwrap().contains_key(&name) {
+            return Ok(name);
+        }
 
-        let mut pipelines = HashMap::new();
+        let wgsl_type = dtype
+            .wgsl_type()
+            .ok_or_else(|| GpuError::InvalidArgument(format!("{} has no {} kernel, dequantize it first", dtype.name(), base)))?;
+        if dtype == DType::F16 && !self.device.features().contains(wgpu::Features::SHADER_F16) {
+            return Err(GpuError::InvalidArgument("f16 kernels need SHADER_F16, not supported by this device".into()));
+        }
+        let template = TYPED_KERNELS
+            .iter()
+            .find(|(kernel, _)| *kernel == base)
+            .map(|(_, template)| *template)
+            .ok_or_else(|| GpuError::UnknownPipeline(base.to_string()))?;
 
-        pipelines.insert("add_u32".to_string(), add_t_pipeline);
-        pipelines.insert("matrix_multiply".to_string(), matrix_mult);
-        pipelines.insert("add".to_string(), add);
-        pipelines.insert("vector_matrix_multiply".to_string(), vector_matrix_multiply);
-        
+        let pipeline = self.compile_builtin(base, &typed_source(template, wgsl_type))?;
+        self.pipelines.write().unwrap().insert(name.clone(), pipeline);
+        Ok(name)
+    }
 
-        GpuManager {
-            device,
-            queue,
-            pipelines,
+    /// pipeline name of the built-in `base` kernel for `workgroup_size`, compiled on first use
+    pub fn sized_kernel(&self, base: &str, workgroup_size: u32) -> Result<String, GpuError> {
+        let name = format!("{}_{}", base, workgroup_size);
+        if self.pipelines.read().unwrap().contains_key(&name) {
+            return Ok(name);
+        }
+
+        let template = SIZED_KERNELS
+            .iter()
+            .find(|(kernel, _)| *kernel == base)
+            .map(|(_, template)| *template)
+            .ok_or_else(|| GpuError::UnknownPipeline(base.to_string()))?;
+
+        let pipeline = self.compile_builtin(base, &sized_source(template, workgroup_size))?;
+        self.pipelines.write().unwrap().insert(name.clone(), pipeline);
+        Ok(name)
+    }
+
+    /// run `f` inside validation and out of memory error scopes,
+    /// an error of `f` itself wins over the captured ones
+    pub async fn scoped<T>(&self, f: impl FnOnce() -> Result<T, GpuError>) -> Result<T, GpuError> {
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let result = f();
+
+        let validation = self.device.pop_error_scope().await;
+        let out_of_memory = self.device.pop_error_scope().await;
+        let result = result?;
+
+        if let Some(e) = out_of_memory.or(validation) {
+            log::error!("GPU Error: {:?}", e);
+            self.check_device()?;
+            return Err(e.into());
         }
-    }   
+
+        Ok(result)
+    }
+
+    pub fn acquire_buffer(&self, size: u64, usage: wgpu::BufferUsages) -> Result<PooledBuffer, GpuError> {
+        self.check_device()?;
+        BufferPool::acquire(&self.pool, &self.device, size, usage)
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.lock().unwrap().stats()
+    }
+
+    pub fn set_pool_cap(&self, cap_bytes: u64) {
+        self.pool.lock().unwrap().set_cap(cap_bytes);
+    }
+
+    /// free all resident tensors and destroy all cached buffers, e.g. when the worker closes
+    pub fn release_buffers(&self) {
+        log::info!("{}: releasing buffers, pool {:?}", self.adapter_info.name, self.pool_stats());
+        self.batches.lock().unwrap().clear();
+        self.tensors.lock().unwrap().clear();
+        self.pool.lock().unwrap().clear();
+    }
+
+    pub fn set_deterministic(&self, deterministic: bool) {
+        self.deterministic.store(deterministic, Ordering::Relaxed);
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic.load(Ordering::Relaxed)
+    }
 }
\ No newline at end of file
@@ -1,54 +1,607 @@
 use once_cell::unsync::OnceCell;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
+use crate::compute_backend::{Backend, ComputeBackend};
+use crate::cpu_backend::CpuBackend;
+use crate::gpu_conv::{Conv2dSpec, PoolMode};
+use crate::gpu_dtype::DType;
+use crate::gpu_elementwise::ElementwiseOp;
+use crate::gpu_error::GpuError;
+use crate::gpu_gemm::GemmSpec;
+use crate::gpu_adapter::AdapterPolicy;
 use crate::gpu_init::GpuManager;
-use dynamic_code::{rune::Module, MODULE_REGISTRY};
+use crate::gpu_schedule::{devices, replace_device, run_backend, set_devices};
+use crate::protocol;
+use dynamic_code::rune::runtime::{FromValue, Object};
+use dynamic_code::rune::Any;
+use dynamic_code::{rune::Module, register_entry, register_module, ModuleEntry};
 
 thread_local! {
-    static GPU: RefCell<OnceCell<GpuManager>> = RefCell::new(OnceCell::new());
+    static BACKEND: RefCell<OnceCell<Backend>> = const { RefCell::new(OnceCell::new()) };
+    /// the policy of `init_gpu`, used again when a lost device is reopened
+    static POLICY: RefCell<AdapterPolicy> = RefCell::new(AdapterPolicy::default());
+    static RECOVERING: Cell<bool> = const { Cell::new(false) };
+    /// last `set_gpu_deterministic`, applied to devices opened after it
+    static DETERMINISTIC: Cell<bool> = const { Cell::new(false) };
 }
 
-pub async fn init_gpu() {
-    let gpu = GpuManager::new().await;
-    GPU.with(|cell| {
-        let _ = cell.borrow_mut().set(gpu);
+/// without a usable adapter the same rune functions run on the cpu backend, except for
+/// non f32 dtypes and runtime wgsl kernels which are not registered so scripts using them
+/// fail to compile at worker/init
+pub async fn init_gpu(policy: &AdapterPolicy) {
+    POLICY.with(|cell| *cell.borrow_mut() = policy.clone());
+    let backend = match GpuManager::open(policy).await {
+        Ok(gpus) => {
+            let deterministic = DETERMINISTIC.with(Cell::get);
+            for gpu in gpus.iter() {
+                gpu.set_deterministic(deterministic);
+            }
+            set_devices(&gpus);
+            Backend::Gpu(gpus[0].clone())
+        }
+        Err(e) => {
+            log::warn!("gpu init failed, falling back to the cpu backend: {}", e);
+            Backend::Cpu(CpuBackend::new())
+        }
+    };
+    let has_gpu = matches!(backend, Backend::Gpu(_));
+    BACKEND.with(|cell| {
+        let _ = cell.borrow_mut().set(backend);
     });
 
+    // names the device, so it differs between workers
+    register_entry(ModuleEntry::new("compute_backend", |module: &mut Module| {
+        module.function(["compute_backend"], backend_name).build()?;
+        Ok(())
+    }).nondeterministic());
+
+    register_module("gpu_add", |module: &mut Module| {
+        module.function(["gpu_add"], add).build()?;
+        Ok(())
+    });
+
+    register_module("gpu_matrix_multiply", |module: &mut Module| {
+        module.function(["gpu_matrix_multiply"], matrix_multiply).build()?;
+        Ok(())
+    });
+
+    register_module("gpu_vec_matrix_multiply", |module: &mut Module| {
+        module.function(["gpu_vec_matrix_multiply"], vec_matrix_multiply).build()?;
+        Ok(())
+    });
+
+    // spec is an object like #{m: 2, n: 3, k: 4, trans_b: true}, see `gemm_spec`
+    register_module("gpu_gemm", |module: &mut Module| {
+        module.function(["gpu_gemm"], gemm).build()?;
+        module.function(["gpu_tensor_gemm"], tensor_gemm).build()?;
+        Ok(())
+    });
+
+    // NCHW f32 tensors, spec is an object like #{kernel: 3, stride: [2, 1], padding: 1}
+    register_module("gpu_conv", |module: &mut Module| {
+        module.function(["gpu_tensor_conv2d"], tensor_conv2d).build()?;
+        module.function(["gpu_tensor_max_pool2d"], tensor_max_pool2d).build()?;
+        module.function(["gpu_tensor_avg_pool2d"], tensor_avg_pool2d).build()?;
+        module.function(["gpu_tensor_im2col"], tensor_im2col).build()?;
+        Ok(())
+    });
+
+    register_module("gpu_tensor", |module: &mut Module| {
+        module.function(["gpu_tensor_upload"], tensor_upload).build()?;
+        module.function(["gpu_tensor_from_matrix"], tensor_from_matrix).build()?;
+        module.function(["gpu_tensor_shape"], tensor_shape).build()?;
+        module.function(["gpu_tensor_retain"], tensor_retain).build()?;
+        module.function(["gpu_tensor_free"], tensor_free).build()?;
+        module.function(["gpu_tensor_download"], tensor_download).build()?;
+        module.function(["gpu_tensor_to_matrix"], tensor_to_matrix).build()?;
+        module.function(["gpu_tensor_add"], tensor_add).build()?;
+        module.function(["gpu_tensor_matmul"], tensor_matmul).build()?;
+        module.function(["gpu_tensor_vec_matmul"], tensor_vec_matmul).build()?;
+        Ok(())
+    });
+
+    if has_gpu {
+        register_gpu_only_modules();
+    } else {
+        log::warn!("cpu backend: gpu_kernel and gpu_dtype functions are not available to scripts");
+    }
+
+    // let b = gpu::batch(); b.add(x, y); b.read(t); b.submit().await
+    register_entry(ModuleEntry::new("gpu_batch", |module: &mut Module| {
+        module.ty::<Batch>()?;
+        module.function(["batch"], Batch::new).build()?;
+        module.associated_function("add", Batch::add)?;
+        module.associated_function("matmul", Batch::matmul)?;
+        module.associated_function("vec_matmul", Batch::vec_matmul)?;
+        module.associated_function("read", Batch::read)?;
+        module.associated_function("submit", Batch::submit)?;
+        Ok(())
+    }).in_crate("gpu"));
+
+    // f32 tensors only, also available on the cpu backend
+    register_module("gpu_nn", |module: &mut Module| {
+        module.function(["gpu_tensor_softmax"], tensor_softmax).build()?;
+        module.function(["gpu_tensor_layer_norm"], tensor_layer_norm).build()?;
+        module.function(["gpu_tensor_rms_norm"], tensor_rms_norm).build()?;
+        module.function(["gpu_tensor_gelu"], tensor_gelu).build()?;
+        module.function(["gpu_tensor_silu"], tensor_silu).build()?;
+        module.function(["gpu_tensor_rope"], tensor_rope).build()?;
+        module.function(["gpu_tensor_attention"], tensor_attention).build()?;
+        Ok(())
+    });
 
-    let register = Box::new(move |module: &mut Module| {
-        module.async_function(["gpu_matrix_multiply"], matrix_multiply)?;
+    // scalars come back to rune, only the scan result stays resident
+    register_module("gpu_reduce", |module: &mut Module| {
+        module.function(["gpu_tensor_sum"], tensor_sum).build()?;
+        module.function(["gpu_tensor_mean"], tensor_mean).build()?;
+        module.function(["gpu_tensor_min"], tensor_min).build()?;
+        module.function(["gpu_tensor_max"], tensor_max).build()?;
+        module.function(["gpu_tensor_dot"], tensor_dot).build()?;
+        module.function(["gpu_tensor_argmax"], tensor_argmax).build()?;
+        module.function(["gpu_tensor_scan"], tensor_scan).build()?;
         Ok(())
     });
-    MODULE_REGISTRY.lock().unwrap().push(register);
 
-    let register = Box::new(move |module: &mut Module| {
-        module.async_function(["gpu_vec_matrix_multiply"], vec_matrix_multiply)?;
+    // operands share shape and dtype, scalars are converted to that dtype
+    register_module("gpu_elementwise", |module: &mut Module| {
+        module.function(["gpu_tensor_sub"], tensor_sub).build()?;
+        module.function(["gpu_tensor_mul"], tensor_mul).build()?;
+        module.function(["gpu_tensor_div"], tensor_div).build()?;
+        module.function(["gpu_tensor_fma"], tensor_fma).build()?;
+        module.function(["gpu_tensor_scale"], tensor_scale).build()?;
+        module.function(["gpu_tensor_clamp"], tensor_clamp).build()?;
         Ok(())
     });
-    MODULE_REGISTRY.lock().unwrap().push(register);
 
 }
 
 
-pub fn get_gpu() -> GpuManager {
-    GPU.with(|cell| {
-		let binding = cell.borrow();
-		let gpu_ref: &GpuManager = binding.get().expect("GpuManager not initialized");
-        (*gpu_ref).clone()
-    })
+/// typed tensors and runtime wgsl kernels, these need a device
+fn register_gpu_only_modules() {
+    register_module("gpu_kernel", |module: &mut Module| {
+        module.function(["gpu_tensor_upload_u32"], tensor_upload_u32).build()?;
+        module.function(["gpu_tensor_upload_i32"], tensor_upload_i32).build()?;
+        module.function(["gpu_tensor_zeros"], tensor_zeros).build()?;
+        module.function(["gpu_tensor_download_int"], tensor_download_int).build()?;
+        module.function(["gpu_run_kernel"], run_kernel).build()?;
+        Ok(())
+    });
+
+    // f16 needs SHADER_F16, u8 only supports upload, download and dequantize
+    register_module("gpu_dtype", |module: &mut Module| {
+        module.function(["gpu_tensor_upload_as"], tensor_upload_as).build()?;
+        module.function(["gpu_tensor_dtype"], tensor_dtype).build()?;
+        module.function(["gpu_tensor_dequantize"], tensor_dequantize).build()?;
+        Ok(())
+    });
 }
 
 
-pub async fn matrix_multiply(a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
-    let gpu = get_gpu();
-    let result = gpu.matrix_multiply(a, b).await;
-        
-    result.expect("gpu run error")
+/// reopen every lost device and report it, called before work starts so a lost device
+/// fails the runs in flight only, a device that cannot be reopened is tried again next time
+pub async fn recover_lost_devices() {
+    if RECOVERING.with(|recovering| recovering.replace(true)) {
+        return;
+    }
+    let policy = POLICY.with(|cell| cell.borrow().clone());
+    for (index, gpu) in devices().into_iter().enumerate() {
+        let Some(reason) = gpu.lost_reason() else {
+            continue;
+        };
+        let error = match gpu.recover(&policy).await {
+            Ok(fresh) => {
+                log::info!("gpu device {} reopened", fresh.adapter_info.name);
+                if index == 0 {
+                    BACKEND.with(|cell| *cell.borrow_mut() = OnceCell::with_value(Backend::Gpu(fresh.clone())));
+                }
+                replace_device(index, fresh);
+                None
+            }
+            Err(e) => {
+                log::error!("reopening gpu device {} failed: {}", gpu.adapter_info.name, e);
+                Some(e.to_string())
+            }
+        };
+        protocol::worker_device_lost(gpu.adapter_info.name.clone(), reason, error);
+    }
+    RECOVERING.with(|recovering| recovering.set(false));
+}
+
+/// kept for devices opened later when the gpu is not up yet,
+/// the cpu backend is always deterministic
+pub fn set_gpu_deterministic(deterministic: bool) {
+    DETERMINISTIC.with(|flag| flag.set(deterministic));
+    for gpu in devices() {
+        gpu.set_deterministic(deterministic);
+    }
+}
+
+/// drop cached buffers and resident tensors, no-op when no backend is up yet
+pub fn release_gpu_buffers() {
+    if let Ok(backend) = get_backend() {
+        backend.release_buffers();
+    }
+    for gpu in devices().iter().skip(1) {
+        gpu.release_buffers();
+    }
+}
+
+/// write the pipelines compiled so far to the cache of every device
+pub fn save_pipeline_caches() {
+    for gpu in devices() {
+        gpu.pipeline_cache.save();
+    }
+}
+
+/// the device of the run being polled, otherwise the one picked at startup
+pub fn get_backend() -> Result<Backend, GpuError> {
+    if let Some(backend) = run_backend() {
+        return Ok(backend);
+    }
+    BACKEND.with(|cell| cell.borrow().get().cloned()).ok_or(GpuError::NotInitialized)
+}
+
+pub fn try_get_gpu() -> Option<GpuManager> {
+    get_gpu().ok()
+}
+
+/// for what only the wgpu backend can do
+pub fn get_gpu() -> Result<GpuManager, GpuError> {
+    get_backend()?.gpu().cloned()
+}
+
+/// "gpu" or "cpu"
+pub fn backend_name() -> Result<String, String> {
+    Ok(get_backend()?.name().to_string())
+}
+
+
+pub async fn add(a: Vec<f32>, b: Vec<f32>) -> Result<Vec<f32>, String> {
+    let backend = get_backend()?;
+    Ok(backend.add(&a, &b).await?)
+}
+
+pub async fn matrix_multiply(a: Vec<Vec<f32>>, b: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, String> {
+    let backend = get_backend()?;
+    Ok(backend.matrix_multiply(a, b).await?)
+}
+
+pub async fn vec_matrix_multiply(a: Vec<f32>, b: Vec<Vec<f32>>) -> Result<Vec<f32>, String> {
+    let backend = get_backend()?;
+    Ok(backend.vec_matrix_multiply(a, b).await?)
+}
+
+fn spec_field<T: FromValue>(spec: &Object, key: &str) -> Result<Option<T>, String> {
+    spec.get(key)
+        .map(|value| dynamic_code::rune::from_value(value.clone()).map_err(|e| format!("spec {}: {}", key, e)))
+        .transpose()
+}
+
+/// m, n and k are required, the rest defaults like `GemmSpec::new`
+fn gemm_spec(spec: &Object) -> Result<GemmSpec, String> {
+    let dim = |key: &str| spec_field::<u32>(spec, key)?.ok_or_else(|| format!("gemm spec needs {}", key));
+    let mut gemm = GemmSpec::new(dim("m")?, dim("n")?, dim("k")?);
+
+    for (key, field) in [
+        ("batch", &mut gemm.batch),
+        ("lda", &mut gemm.lda),
+        ("ldb", &mut gemm.ldb),
+        ("ldc", &mut gemm.ldc),
+        ("stride_a", &mut gemm.stride_a),
+        ("stride_b", &mut gemm.stride_b),
+        ("stride_c", &mut gemm.stride_c),
+    ] {
+        if let Some(value) = spec_field(spec, key)? {
+            *field = value;
+        }
+    }
+    for (key, field) in [("trans_a", &mut gemm.trans_a), ("trans_b", &mut gemm.trans_b)] {
+        if let Some(value) = spec_field(spec, key)? {
+            *field = value;
+        }
+    }
+    for (key, field) in [("alpha", &mut gemm.alpha), ("beta", &mut gemm.beta)] {
+        if let Some(value) = spec_field(spec, key)? {
+            *field = value;
+        }
+    }
+    Ok(gemm)
+}
+
+pub async fn gemm(spec: Object, a: Vec<f32>, b: Vec<f32>, c: Option<Vec<f32>>, bias: Option<Vec<f32>>) -> Result<Vec<f32>, String> {
+    let spec = gemm_spec(&spec)?;
+    Ok(get_backend()?.gemm(&spec, &a, &b, c.as_deref(), bias.as_deref()).await?)
+}
+
+pub async fn tensor_gemm(spec: Object, a: i64, b: i64, c: Option<i64>, bias: Option<i64>) -> Result<i64, String> {
+    let spec = gemm_spec(&spec)?;
+    let id = get_backend()?.tensor_gemm(&spec, a as u64, b as u64, c.map(|c| c as u64), bias.map(|b| b as u64)).await?;
+    Ok(id as i64)
+}
+
+/// `key: 2` or `key: [2, 1]`, as (height, width)
+fn spec_pair(spec: &Object, key: &str) -> Result<Option<(u32, u32)>, String> {
+    let Some(value) = spec.get(key) else {
+        return Ok(None);
+    };
+    if let Ok(both) = dynamic_code::rune::from_value::<u32>(value.clone()) {
+        return Ok(Some((both, both)));
+    }
+    match dynamic_code::rune::from_value::<Vec<u32>>(value.clone()) {
+        Ok(pair) if pair.len() == 2 => Ok(Some((pair[0], pair[1]))),
+        _ => Err(format!("spec {} must be a number or [height, width]", key)),
+    }
+}
+
+/// stride, padding, dilation and groups, missing ones default like `Conv2dSpec::default`
+fn conv_spec(spec: &Object) -> Result<Conv2dSpec, String> {
+    let mut conv = Conv2dSpec::default();
+    for (key, field) in [("stride", &mut conv.stride), ("padding", &mut conv.padding), ("dilation", &mut conv.dilation)] {
+        if let Some(pair) = spec_pair(spec, key)? {
+            *field = pair;
+        }
+    }
+    if let Some(groups) = spec_field(spec, "groups")? {
+        conv.groups = groups;
+    }
+    Ok(conv)
+}
+
+/// window size of pooling and im2col, pooling strides by the window unless told otherwise
+fn window_spec(spec: &Object, stride_is_window: bool) -> Result<((u32, u32), Conv2dSpec), String> {
+    let kernel = spec_pair(spec, "kernel")?.ok_or("spec needs kernel")?;
+    let mut conv = conv_spec(spec)?;
+    if stride_is_window && spec.get("stride").is_none() {
+        conv.stride = kernel;
+    }
+    Ok((kernel, conv))
+}
+
+pub fn tensor_upload(data: Vec<f32>, shape: Vec<i64>) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_upload(&data, to_shape(shape)?)? as i64)
+}
+
+pub fn tensor_from_matrix(m: Vec<Vec<f32>>) -> Result<i64, String> {
+    let long = m.len() as u32;
+    let width = m.first().map_or(0, |r| r.len() as u32);
+    let data = m.into_iter().flatten().collect::<Vec<_>>();
+    Ok(get_backend()?.tensor_upload(&data, vec![long, width])? as i64)
+}
+
+pub fn tensor_shape(id: i64) -> Result<Vec<i64>, String> {
+    Ok(get_backend()?.tensor_shape(id as u64)?.into_iter().map(|d| d as i64).collect())
+}
+
+pub fn tensor_retain(id: i64) -> Result<(), String> {
+    Ok(get_backend()?.tensor_retain(id as u64)?)
+}
+
+pub fn tensor_free(id: i64) -> Result<(), String> {
+    Ok(get_backend()?.tensor_free(id as u64)?)
+}
+
+pub async fn tensor_download(id: i64) -> Result<Vec<f32>, String> {
+    Ok(get_backend()?.tensor_download(id as u64).await?)
+}
+
+pub async fn tensor_to_matrix(id: i64) -> Result<Vec<Vec<f32>>, String> {
+    let backend = get_backend()?;
+    let shape = backend.tensor_shape(id as u64)?;
+    let width = match shape.as_slice() {
+        [_, width] => *width as usize,
+        _ => return Err(format!("expected a 2d tensor, got shape {:?}", shape)),
+    };
+
+    let data = backend.tensor_download(id as u64).await?;
+    Ok(data.chunks(width.max(1)).map(|row| row.to_vec()).collect())
+}
+
+pub async fn tensor_add(a: i64, b: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_add(a as u64, b as u64).await? as i64)
 }
 
-pub async fn vec_matrix_multiply(a: Vec<f32>, b: Vec<Vec<f32>>) -> Vec<f32> {
-    let gpu = get_gpu();
-    let result = gpu.vec_matrix_multiply(a, b).await;
-        
-    result.expect("gpu run error")
-}
\ No newline at end of file
+async fn elementwise(op: ElementwiseOp, inputs: &[i64], alpha: f32, beta: f32) -> Result<i64, String> {
+    let ids: Vec<u64> = inputs.iter().map(|id| *id as u64).collect();
+    Ok(get_backend()?.tensor_elementwise(op, &ids, alpha, beta).await? as i64)
+}
+
+pub async fn tensor_sub(a: i64, b: i64) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Sub, &[a, b], 0.0, 0.0).await
+}
+
+pub async fn tensor_mul(a: i64, b: i64) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Mul, &[a, b], 0.0, 0.0).await
+}
+
+pub async fn tensor_div(a: i64, b: i64) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Div, &[a, b], 0.0, 0.0).await
+}
+
+/// a * b + c
+pub async fn tensor_fma(a: i64, b: i64, c: i64) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Fma, &[a, b, c], 0.0, 0.0).await
+}
+
+/// a * alpha + beta
+pub async fn tensor_scale(a: i64, alpha: f32, beta: f32) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Scale, &[a], alpha, beta).await
+}
+
+pub async fn tensor_clamp(a: i64, min: f32, max: f32) -> Result<i64, String> {
+    elementwise(ElementwiseOp::Clamp, &[a], min, max).await
+}
+
+pub async fn tensor_matmul(a: i64, b: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_matmul(a as u64, b as u64).await? as i64)
+}
+
+pub async fn tensor_vec_matmul(v: i64, m: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_vec_matmul(v as u64, m as u64).await? as i64)
+}
+
+/// script side of a recorded batch, `gpu::batch()` then `add`, `matmul`, `vec_matmul`
+/// and `read` on it, nothing reaches the gpu until `submit().await`
+#[derive(Any)]
+#[rune(module = dynamic_code::rune, item = ::gpu)]
+pub struct Batch {
+    id: u64,
+}
+
+impl Batch {
+    pub fn new() -> Result<Batch, String> {
+        Ok(Batch { id: get_backend()?.batch_new() })
+    }
+
+    pub fn add(&self, a: i64, b: i64) -> Result<i64, String> {
+        Ok(get_backend()?.batch_add(self.id, a as u64, b as u64)? as i64)
+    }
+
+    pub fn matmul(&self, a: i64, b: i64) -> Result<i64, String> {
+        Ok(get_backend()?.batch_matmul(self.id, a as u64, b as u64)? as i64)
+    }
+
+    pub fn vec_matmul(&self, v: i64, m: i64) -> Result<i64, String> {
+        Ok(get_backend()?.batch_vec_matmul(self.id, v as u64, m as u64)? as i64)
+    }
+
+    pub fn read(&self, tensor: i64) -> Result<(), String> {
+        Ok(get_backend()?.batch_read(self.id, tensor as u64)?)
+    }
+
+    /// takes the batch, it can not record or submit again afterwards
+    pub async fn submit(self) -> Result<Vec<Vec<f32>>, String> {
+        Ok(get_backend()?.batch_submit(self.id).await?)
+    }
+}
+
+/// a negative or too large dimension is the script's error, not a silently wrapped shape
+fn to_shape(shape: Vec<i64>) -> Result<Vec<u32>, GpuError> {
+    shape
+        .into_iter()
+        .map(|d| u32::try_from(d).map_err(|_| GpuError::ShapeMismatch(format!("dimension {} out of range", d))))
+        .collect()
+}
+
+pub fn tensor_upload_u32(data: Vec<i64>, shape: Vec<i64>) -> Result<i64, String> {
+    let data: Vec<u32> = data.into_iter().map(|v| v as u32).collect();
+    Ok(get_gpu()?.tensor_upload_bytes(bytemuck::cast_slice(&data), to_shape(shape)?, DType::U32)? as i64)
+}
+
+pub fn tensor_upload_i32(data: Vec<i64>, shape: Vec<i64>) -> Result<i64, String> {
+    let data: Vec<i32> = data.into_iter().map(|v| v as i32).collect();
+    Ok(get_gpu()?.tensor_upload_bytes(bytemuck::cast_slice(&data), to_shape(shape)?, DType::I32)? as i64)
+}
+
+pub fn tensor_zeros(shape: Vec<i64>, dtype: String) -> Result<i64, String> {
+    Ok(get_gpu()?.tensor_zeros(to_shape(shape)?, DType::parse(&dtype)?)? as i64)
+}
+
+pub async fn tensor_download_int(id: i64) -> Result<Vec<i64>, String> {
+    let (bytes, dtype) = get_gpu()?.tensor_download_bytes(id as u64).await?;
+    let words = bytes.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]);
+
+    match dtype {
+        DType::U32 => Ok(words.map(|w| u32::from_le_bytes(w) as i64).collect()),
+        DType::I32 => Ok(words.map(|w| i32::from_le_bytes(w) as i64).collect()),
+        _ => Err(format!("tensor {} is {}, not an integer type", id, dtype.name())),
+    }
+}
+
+/// `gpu_run_kernel("my_entry", [in_a, in_b, out], [x, y, z]).await`
+pub async fn run_kernel(name: String, tensors: Vec<i64>, workgroups: Vec<i64>) -> Result<(), String> {
+    let tensors: Vec<u64> = tensors.into_iter().map(|t| t as u64).collect();
+    let dim = |i: usize| workgroups.get(i).map_or(1, |v| *v as u32);
+
+    Ok(get_gpu()?.run_kernel(&name, &tensors, (dim(0), dim(1), dim(2))).await?)
+}
+
+/// `gpu_tensor_upload_as(data, shape, "f16")`, values are converted on the host
+pub fn tensor_upload_as(data: Vec<f32>, shape: Vec<i64>, dtype: String) -> Result<i64, String> {
+    Ok(get_gpu()?.tensor_upload_as(&data, to_shape(shape)?, DType::parse(&dtype)?)? as i64)
+}
+
+pub fn tensor_dtype(id: i64) -> Result<String, String> {
+    Ok(get_gpu()?.tensor_dtype(id as u64)?.name().to_string())
+}
+
+pub async fn tensor_dequantize(id: i64, scale: f32, zero_point: f32) -> Result<i64, String> {
+    Ok(get_gpu()?.tensor_dequantize(id as u64, scale, zero_point).await? as i64)
+}
+
+pub async fn tensor_softmax(id: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_softmax(id as u64).await? as i64)
+}
+
+pub async fn tensor_layer_norm(id: i64, gamma: i64, beta: i64, eps: f32) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_layer_norm(id as u64, gamma as u64, beta as u64, eps).await? as i64)
+}
+
+pub async fn tensor_rms_norm(id: i64, gamma: i64, eps: f32) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_rms_norm(id as u64, gamma as u64, eps).await? as i64)
+}
+
+pub async fn tensor_gelu(id: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_gelu(id as u64).await? as i64)
+}
+
+pub async fn tensor_silu(id: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_silu(id as u64).await? as i64)
+}
+
+/// `offset` is the position of the first row, `base` is usually 10000
+pub async fn tensor_rope(id: i64, offset: i64, base: f32) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_rope(id as u64, offset as u32, base).await? as i64)
+}
+
+pub async fn tensor_attention(q: i64, k: i64, v: i64, causal: bool) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_attention(q as u64, k as u64, v as u64, causal).await? as i64)
+}
+
+pub async fn tensor_sum(id: i64) -> Result<f32, String> {
+    Ok(get_backend()?.tensor_sum(id as u64).await?)
+}
+
+pub async fn tensor_mean(id: i64) -> Result<f32, String> {
+    Ok(get_backend()?.tensor_mean(id as u64).await?)
+}
+
+pub async fn tensor_min(id: i64) -> Result<f32, String> {
+    Ok(get_backend()?.tensor_min(id as u64).await?)
+}
+
+pub async fn tensor_max(id: i64) -> Result<f32, String> {
+    Ok(get_backend()?.tensor_max(id as u64).await?)
+}
+
+pub async fn tensor_dot(a: i64, b: i64) -> Result<f32, String> {
+    Ok(get_backend()?.tensor_dot(a as u64, b as u64).await?)
+}
+
+pub async fn tensor_argmax(id: i64) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_argmax(id as u64).await? as i64)
+}
+
+/// `gpu_tensor_scan(t, false)` is the inclusive prefix sum, `true` the exclusive one
+pub async fn tensor_scan(id: i64, exclusive: bool) -> Result<i64, String> {
+    Ok(get_backend()?.tensor_scan(id as u64, exclusive).await? as i64)
+}
+
+pub async fn tensor_conv2d(x: i64, weight: i64, bias: Option<i64>, spec: Object) -> Result<i64, String> {
+    let spec = conv_spec(&spec)?;
+    Ok(get_backend()?.tensor_conv2d(x as u64, weight as u64, bias.map(|b| b as u64), &spec).await? as i64)
+}
+
+pub async fn tensor_max_pool2d(x: i64, spec: Object) -> Result<i64, String> {
+    let (kernel, spec) = window_spec(&spec, true)?;
+    Ok(get_backend()?.tensor_pool2d(x as u64, PoolMode::Max, kernel, &spec).await? as i64)
+}
+
+pub async fn tensor_avg_pool2d(x: i64, spec: Object) -> Result<i64, String> {
+    let (kernel, spec) = window_spec(&spec, true)?;
+    Ok(get_backend()?.tensor_pool2d(x as u64, PoolMode::Avg, kernel, &spec).await? as i64)
+}
+
+pub async fn tensor_im2col(x: i64, spec: Object) -> Result<i64, String> {
+    let (kernel, spec) = window_spec(&spec, false)?;
+    Ok(get_backend()?.tensor_im2col(x as u64, kernel, &spec).await? as i64)
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::{is_builtin_name, GpuManager};
+use crate::gpu_reflect::{create_reflected_pipeline, reflect_bind_groups, validate_wgsl};
+
+/// one buffer of a runtime kernel, layout and access come from the wgsl itself,
+/// this only says which dtype the bound tensor must have
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KernelBinding {
+    #[serde(default)]
+    pub group: u32,
+    pub binding: u32,
+    #[serde(default)]
+    pub dtype: DType,
+}
+
+/// wgsl sent with worker/init, `name` is the compute entry point
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KernelSource {
+    pub name: String,
+    pub wgsl: String,
+    pub bindings: Vec<KernelBinding>,
+}
+
+impl GpuManager {
+    /// compile `kernel` into a pipeline callable by its entry point name
+    pub async fn load_kernel(&self, kernel: &KernelSource) -> Result<(), GpuError> {
+        let (module, info) = validate_wgsl(&kernel.wgsl).map_err(GpuError::Shader)?;
+        let groups = reflect_bind_groups(&module, &info, &kernel.name).map_err(GpuError::Shader)?;
+
+        // runtime kernels share the pipeline map with the built-ins, which must not be shadowed
+        if is_builtin_name(&kernel.name) {
+            return Err(GpuError::Shader(format!("kernel name {} is taken by a built-in kernel", kernel.name)));
+        }
+
+        for binding in kernel.bindings.iter() {
+            let declared = groups
+                .get(binding.group as usize)
+                .is_some_and(|entries| entries.iter().any(|e| e.binding == binding.binding));
+            if !declared {
+                return Err(GpuError::Shader(format!(
+                    "kernel {} does not use binding {}:{}",
+                    kernel.name, binding.group, binding.binding
+                )));
+            }
+        }
+
+        for (group, entries) in groups.iter().enumerate() {
+            for entry in entries.iter() {
+                let described = kernel.bindings.iter().any(|b| b.group as usize == group && b.binding == entry.binding);
+                if !described {
+                    return Err(GpuError::Shader(format!(
+                        "kernel {} uses binding {}:{} missing from its description",
+                        kernel.name, group, entry.binding
+                    )));
+                }
+            }
+        }
+
+        let pipeline = self
+            .scoped(|| {
+                let shader_module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(&kernel.name),
+                    source: wgpu::ShaderSource::Wgsl(kernel.wgsl.as_str().into()),
+                });
+
+                let cache = self.pipeline_cache.get();
+                create_reflected_pipeline(&self.device, &shader_module, &kernel.wgsl, &kernel.name, cache).map_err(GpuError::Shader)
+            })
+            .await
+            .map_err(|e| GpuError::Shader(format!("kernel {} failed to compile: {}", kernel.name, e)))?;
+
+        self.pipelines.write().unwrap().insert(kernel.name.clone(), pipeline);
+        self.kernels.write().unwrap().insert(kernel.name.clone(), kernel.clone());
+
+        Ok(())
+    }
+
+    /// dispatch a runtime kernel, `tensors[i]` is bound to the i-th binding of its description
+    pub async fn run_kernel(&self, name: &str, tensors: &[u64], workgroups: (u32, u32, u32)) -> Result<(), GpuError> {
+        let bindings = self
+            .kernels
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|kernel| kernel.bindings.clone())
+            .ok_or_else(|| GpuError::UnknownPipeline(name.to_string()))?;
+
+        if bindings.len() != tensors.len() {
+            return Err(GpuError::InvalidArgument(format!(
+                "kernel {} takes {} buffers, got {}",
+                name,
+                bindings.len(),
+                tensors.len()
+            )));
+        }
+
+        let mut buffers = Vec::with_capacity(tensors.len());
+        for (binding, tensor) in bindings.iter().zip(tensors) {
+            let (buffer, _) = self.tensor(*tensor, binding.dtype)?;
+            buffers.push(buffer);
+        }
+
+        let mut groups: Vec<(u32, Vec<wgpu::BindGroupEntry>)> = Vec::new();
+        for (binding, buffer) in bindings.iter().zip(buffers.iter()) {
+            let entry = wgpu::BindGroupEntry { binding: binding.binding, resource: buffer.binding() };
+            match groups.iter_mut().find(|(group, _)| *group == binding.group) {
+                Some((_, entries)) => entries.push(entry),
+                None => groups.push((binding.group, vec![entry])),
+            }
+        }
+        let group_refs: Vec<(u32, &[wgpu::BindGroupEntry])> =
+            groups.iter().map(|(group, entries)| (*group, entries.as_slice())).collect();
+
+        self.dispatch_groups(name, workgroups, &group_refs).await
+    }
+}
@@ -0,0 +1,165 @@
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::element_count;
+
+/// uniform of the nn kernels, mirrors `NnParams` in gpu_shade
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct NnParams {
+    rows: u32,
+    cols: u32,
+    inner: u32,
+    flag: u32,
+    eps: f32,
+    scale: f32,
+    base: f32,
+    out_cols: u32,
+}
+
+/// leading dims flattened into rows, the last dim is what softmax and the norms run along
+pub(crate) fn rows_cols(shape: &[u32]) -> Result<(u32, u32), GpuError> {
+    match shape.split_last() {
+        Some((&cols, rest)) if cols > 0 => Ok((element_count(rest)?, cols)),
+        _ => Err(GpuError::ShapeMismatch(format!("expected a non empty last dim, got shape {:?}", shape))),
+    }
+}
+
+/// gamma / beta of a norm hold one value per column
+pub(crate) fn check_weight(name: &str, shape: &[u32], cols: u32) -> Result<(), GpuError> {
+    if element_count(shape)? != cols {
+        return Err(GpuError::ShapeMismatch(format!("{} has shape {:?}, expected {} values", name, shape, cols)));
+    }
+    Ok(())
+}
+
+/// rope takes a seq x dim tensor with an even dim
+pub(crate) fn rope_dims(shape: &[u32]) -> Result<(u32, u32), GpuError> {
+    match shape {
+        [seq, dim] if dim % 2 == 0 => Ok((*seq, *dim)),
+        _ => Err(GpuError::ShapeMismatch(format!("rope expects seq x even dim, got shape {:?}", shape))),
+    }
+}
+
+/// (seq_q, d, seq_k, dv) of q: seq_q x d, k: seq_k x d, v: seq_k x dv
+pub(crate) fn attention_dims(q: &[u32], k: &[u32], v: &[u32]) -> Result<(u32, u32, u32, u32), GpuError> {
+    match (q, k, v) {
+        ([seq_q, d], [seq_k, d_k], [seq_v, dv]) if d == d_k && seq_k == seq_v => Ok((*seq_q, *d, *seq_k, *dv)),
+        _ => Err(GpuError::ShapeMismatch(format!(
+            "attention expects q: s x d, k: t x d, v: t x dv, got {:?}, {:?}, {:?}",
+            q, k, v
+        ))),
+    }
+}
+
+/// last key a causal query row may attend to (exclusive), keys are aligned to the end so
+/// a query block appended to a cache sees the whole cache
+pub(crate) fn causal_limit(row: u32, seq_q: u32, seq_k: u32) -> u32 {
+    seq_k.min(row + 1 + seq_k - seq_k.min(seq_q))
+}
+
+impl GpuManager {
+    /// run a built-in nn kernel: binding 0 is the params, then `inputs` in order, then the f32 output
+    async fn run_nn(
+        &self,
+        kernel: &str,
+        params: NnParams,
+        workgroups: (u32, u32, u32),
+        inputs: &[&PooledBuffer],
+        out_shape: Vec<u32>,
+    ) -> Result<u64, GpuError> {
+        let params_buffer = self.uniform_buffer(&params)?;
+        let result = self.alloc_tensor(&out_shape, DType::F32)?;
+
+        {
+            let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: params_buffer.binding() }];
+            for (i, input) in inputs.iter().enumerate() {
+                entries.push(wgpu::BindGroupEntry { binding: i as u32 + 1, resource: input.binding() });
+            }
+            entries.push(wgpu::BindGroupEntry { binding: inputs.len() as u32 + 1, resource: result.binding() });
+
+            self.dispatch(kernel, 0, workgroups, &entries).await?;
+        }
+
+        Ok(self.tensors.lock().unwrap().insert(result, out_shape, DType::F32))
+    }
+
+    /// softmax along the last dim
+    pub async fn tensor_softmax(&self, id: u64) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let (rows, cols) = rows_cols(&shape)?;
+
+        let params = NnParams { rows, cols, ..Default::default() };
+        self.run_nn("softmax", params, self.grid_1d(rows, 64), &[&*buffer], shape).await
+    }
+
+    pub async fn tensor_layer_norm(&self, id: u64, gamma: u64, beta: u64, eps: f32) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let (gamma_buffer, gamma_shape) = self.tensor(gamma, DType::F32)?;
+        let (beta_buffer, beta_shape) = self.tensor(beta, DType::F32)?;
+        let (rows, cols) = rows_cols(&shape)?;
+        check_weight("gamma", &gamma_shape, cols)?;
+        check_weight("beta", &beta_shape, cols)?;
+
+        let params = NnParams { rows, cols, eps, ..Default::default() };
+        let inputs = [&*buffer, &*gamma_buffer, &*beta_buffer];
+        self.run_nn("layer_norm", params, self.grid_1d(rows, 64), &inputs, shape).await
+    }
+
+    pub async fn tensor_rms_norm(&self, id: u64, gamma: u64, eps: f32) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let (gamma_buffer, gamma_shape) = self.tensor(gamma, DType::F32)?;
+        let (rows, cols) = rows_cols(&shape)?;
+        check_weight("gamma", &gamma_shape, cols)?;
+
+        let params = NnParams { rows, cols, eps, ..Default::default() };
+        self.run_nn("rms_norm", params, self.grid_1d(rows, 64), &[&*buffer, &*gamma_buffer], shape).await
+    }
+
+    async fn activation(&self, kernel: &str, id: u64) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let len = element_count(&shape)?;
+
+        let params = NnParams { rows: 1, cols: len, ..Default::default() };
+        self.run_nn(kernel, params, self.grid_1d(len, 64), &[&*buffer], shape).await
+    }
+
+    /// gelu, tanh approximation
+    pub async fn tensor_gelu(&self, id: u64) -> Result<u64, GpuError> {
+        self.activation("gelu", id).await
+    }
+
+    pub async fn tensor_silu(&self, id: u64) -> Result<u64, GpuError> {
+        self.activation("silu", id).await
+    }
+
+    /// rotary embedding of a seq x dim tensor, row `i` sits at position `offset + i`
+    pub async fn tensor_rope(&self, id: u64, offset: u32, base: f32) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let (seq, dim) = rope_dims(&shape)?;
+
+        let params = NnParams { rows: seq, cols: dim, flag: offset, base, ..Default::default() };
+        self.run_nn("rope", params, self.grid_1d(seq * dim / 2, 64), &[&*buffer], shape).await
+    }
+
+    /// softmax(q k^T / sqrt(d)) v, optionally with a causal mask
+    pub async fn tensor_attention(&self, q: u64, k: u64, v: u64, causal: bool) -> Result<u64, GpuError> {
+        let (q_buffer, q_shape) = self.tensor(q, DType::F32)?;
+        let (k_buffer, k_shape) = self.tensor(k, DType::F32)?;
+        let (v_buffer, v_shape) = self.tensor(v, DType::F32)?;
+        let (seq_q, d, seq_k, dv) = attention_dims(&q_shape, &k_shape, &v_shape)?;
+
+        let params = NnParams {
+            rows: seq_q,
+            cols: d,
+            inner: seq_k,
+            flag: causal as u32,
+            scale: 1.0 / (d as f32).sqrt(),
+            out_cols: dv,
+            ..Default::default()
+        };
+        let inputs = [&*q_buffer, &*k_buffer, &*v_buffer];
+        self.run_nn("attention", params, self.grid_1d(seq_q, 64), &inputs, vec![seq_q, dv]).await
+    }
+}
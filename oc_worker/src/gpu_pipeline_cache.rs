@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+/// `oc_worker_pipelines` in the cache dir of the current user, the shared temp dir is not
+/// used since anyone could plant a cache there for the driver to load
+fn user_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .or_else(|| std::env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("oc_worker_pipelines"))
+}
+
+/// wgpu pipeline cache kept in a file per adapter and driver, so a restarted worker skips
+/// the driver compile of pipelines it built before, only vulkan devices support it
+pub struct PipelineCache {
+    cache: Option<wgpu::PipelineCache>,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// load the cache of this adapter from `dir`, the user cache dir when `None`,
+    /// disabled when there is neither or the device has no PIPELINE_CACHE, which every browser device lacks
+    pub fn open(device: &wgpu::Device, info: &wgpu::AdapterInfo, dir: Option<&str>) -> Self {
+        let disabled = Self { cache: None, path: None };
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return disabled;
+        }
+        let Some(key) = wgpu::util::pipeline_cache_key(info) else {
+            return disabled;
+        };
+        let Some(dir) = dir.map(PathBuf::from).or_else(user_cache_dir) else {
+            return disabled;
+        };
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            log::warn!("pipeline cache dir {} unusable: {}", dir.display(), e);
+            return disabled;
+        }
+
+        let path = dir.join(key);
+        let data = std::fs::read(&path).ok();
+        // SAFETY: the dir is configured or private to the user and the file name is the wgpu cache
+        // key of this adapter and driver, so the data was written by this worker on a compatible
+        // device, `fallback` drops it when the driver rejects it
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("pipeline cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        Self { cache: Some(cache), path: Some(path) }
+    }
+
+    pub fn get(&self) -> Option<&wgpu::PipelineCache> {
+        self.cache.as_ref()
+    }
+
+    /// write the cache back, through a temp file so a crash never leaves half of it,
+    /// called after a batch of compiles rather than after each one
+    pub fn save(&self) {
+        let (Some(cache), Some(path)) = (&self.cache, &self.path) else {
+            return;
+        };
+        let Some(data) = cache.get_data() else {
+            return;
+        };
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, path)) {
+            log::warn!("saving pipeline cache {} failed: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::progress;
+
+/// timings of one run, sent with worker/run for billing and scheduling
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunMetrics {
+    /// gpu time of all dispatches from timestamp queries, absent when the device has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_ms: Option<f64>,
+    /// host time spent writing inputs into gpu buffers
+    pub upload_ms: f64,
+    /// host time waiting for readbacks to map, this includes waiting for queued work
+    pub readback_ms: f64,
+    pub dispatches: u32,
+}
+
+/// metrics and unread timestamps of one profiled run
+#[derive(Default)]
+struct RunProfile {
+    /// resolve and readback buffer per timed pass, read when the run ends
+    pending: Vec<(PooledBuffer, PooledBuffer)>,
+    metrics: RunMetrics,
+}
+
+/// collects `RunMetrics` of every run that asked for them, keyed by event id,
+/// work is charged to the run `progress::current_run` names so runs sharing the device stay apart
+pub struct Profiler {
+    /// begin and end of one pass, every pass reuses it since it is resolved right after
+    query_set: Option<wgpu::QuerySet>,
+    runs: HashMap<u64, RunProfile>,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let query_set = device.features().contains(wgpu::Features::TIMESTAMP_QUERY).then(|| {
+            device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("pass timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            })
+        });
+        Self { query_set, runs: HashMap::new() }
+    }
+
+    /// the profile of the run being polled, `None` when it did not ask for one
+    fn current(&mut self) -> Option<&mut RunProfile> {
+        let event_id = progress::current_run()?;
+        self.runs.get_mut(&event_id)
+    }
+}
+
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+impl GpuManager {
+    /// start collecting metrics for the run `event_id`
+    pub fn begin_profile(&self, event_id: u64) {
+        self.profiler.lock().unwrap().runs.insert(event_id, RunProfile::default());
+    }
+
+    /// stop collecting for `event_id` and sum the timestamps of its passes
+    pub async fn end_profile(&self, event_id: u64) -> RunMetrics {
+        let (run, timestamps) = {
+            let mut profiler = self.profiler.lock().unwrap();
+            (profiler.runs.remove(&event_id).unwrap_or_default(), profiler.query_set.is_some())
+        };
+        let RunProfile { pending, mut metrics } = run;
+        if !timestamps {
+            return metrics;
+        }
+
+        let period = self.queue.get_timestamp_period() as f64;
+        let mut ticks = 0u64;
+        for (_, readback) in pending.iter() {
+            if let Err(e) = self.map_read(readback).await {
+                log::error!("reading pass timestamps failed: {}", e);
+                return metrics;
+            }
+            let data = readback.slice(..readback.byte_len()).get_mapped_range();
+            let stamp = |i: usize| u64::from_le_bytes(data[i * 8..(i + 1) * 8].try_into().unwrap());
+            ticks += stamp(1).saturating_sub(stamp(0));
+            drop(data);
+            readback.unmap();
+        }
+        metrics.compute_ms = Some(ticks as f64 * period / 1e6);
+        metrics
+    }
+
+    /// timestamp writes for the next pass, `None` unless profiling on a device with timestamp queries
+    pub(crate) fn pass_timestamps(&self) -> Option<wgpu::QuerySet> {
+        let mut profiler = self.profiler.lock().unwrap();
+        profiler.current()?.metrics.dispatches += 1;
+        profiler.query_set.clone()
+    }
+
+    /// resolve the timestamps of the pass just recorded into a buffer read at `end_profile`
+    pub(crate) fn resolve_timestamps(&self, encoder: &mut wgpu::CommandEncoder, query_set: &wgpu::QuerySet) -> Result<(), GpuError> {
+        let bytes = 2 * wgpu::QUERY_SIZE as u64;
+        let resolve = self.acquire_buffer(bytes, wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC)?;
+        let readback = self.acquire_buffer(bytes, wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ)?;
+
+        encoder.resolve_query_set(query_set, 0..2, &resolve, 0);
+        encoder.copy_buffer_to_buffer(&resolve, 0, &readback, 0, bytes);
+        if let Some(run) = self.profiler.lock().unwrap().current() {
+            run.pending.push((resolve, readback));
+        }
+        Ok(())
+    }
+
+    /// `queue.write_buffer` counted as upload time of the run
+    pub(crate) fn write_timed(&self, buffer: &wgpu::Buffer, data: &[u8]) {
+        let start = now_ms();
+        self.queue.write_buffer(buffer, 0, data);
+        self.record(|metrics| metrics.upload_ms += now_ms() - start);
+    }
+
+    pub(crate) fn record_readback(&self, start_ms: f64) {
+        self.record(|metrics| metrics.readback_ms += now_ms() - start_ms);
+    }
+
+    fn record(&self, f: impl FnOnce(&mut RunMetrics)) {
+        if let Some(run) = self.profiler.lock().unwrap().current() {
+            f(&mut run.metrics);
+        }
+    }
+}
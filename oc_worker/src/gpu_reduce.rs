@@ -0,0 +1,306 @@
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_dtype::DType;
+use crate::gpu_error::GpuError;
+use crate::gpu_init::GpuManager;
+use crate::gpu_tensor::element_count;
+
+/// values each invocation folds before the workgroup tree
+pub(crate) const PER_THREAD: u32 = 4;
+/// workgroup size in deterministic mode, every device then builds the same tree
+pub(crate) const DETERMINISTIC_WORKGROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ReduceParams {
+    len: u32,
+    per_thread: u32,
+    groups: u32,
+    first: u32,
+    identity: f32,
+    _pad: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScanParams {
+    len: u32,
+    exclusive: u32,
+    groups: u32,
+    _pad: u32,
+}
+
+/// kernels of one reduction, `rest` folds the partials of the previous pass
+struct Reduction {
+    first: &'static str,
+    rest: &'static str,
+    rest_inputs: usize,
+    /// u32 words per partial
+    words: u64,
+    identity: f32,
+}
+
+const SUM: Reduction = Reduction { first: "reduce_sum", rest: "reduce_sum", rest_inputs: 1, words: 1, identity: 0.0 };
+const MIN: Reduction = Reduction { first: "reduce_min", rest: "reduce_min", rest_inputs: 1, words: 1, identity: f32::INFINITY };
+const MAX: Reduction = Reduction { first: "reduce_max", rest: "reduce_max", rest_inputs: 1, words: 1, identity: f32::NEG_INFINITY };
+const DOT: Reduction = Reduction { first: "reduce_dot", rest: "reduce_sum", rest_inputs: 1, words: 1, identity: 0.0 };
+// the pair input is unused on the first pass, `x` is bound in its place
+const ARGMAX: Reduction = Reduction { first: "reduce_argmax", rest: "reduce_argmax", rest_inputs: 2, words: 2, identity: f32::NEG_INFINITY };
+
+/// sum the way the gpu does with the deterministic workgroup size, so the backends agree
+pub(crate) fn tree_sum(mut values: Vec<f32>) -> f32 {
+    let wg = DETERMINISTIC_WORKGROUP_SIZE as usize;
+    let per_thread = PER_THREAD as usize;
+    loop {
+        let partials: Vec<f32> = values
+            .chunks(wg * per_thread)
+            .map(|block| {
+                let mut s: Vec<f32> = (0..wg)
+                    .map(|t| block.iter().skip(t * per_thread).take(per_thread).fold(0.0, |acc, v| acc + v))
+                    .collect();
+                let mut stride = wg / 2;
+                while stride > 0 {
+                    for i in 0..stride {
+                        s[i] += s[i + stride];
+                    }
+                    stride /= 2;
+                }
+                s[0]
+            })
+            .collect();
+
+        if partials.len() <= 1 {
+            return partials.first().copied().unwrap_or(0.0);
+        }
+        values = partials;
+    }
+}
+
+pub(crate) fn check_not_empty(op: &str, len: usize) -> Result<(), GpuError> {
+    if len == 0 {
+        return Err(GpuError::InvalidArgument(format!("{} of an empty tensor", op)));
+    }
+    Ok(())
+}
+
+impl GpuManager {
+    /// fixed in deterministic mode, otherwise the largest power of two the device runs in one workgroup
+    fn reduce_workgroup_size(&self) -> u32 {
+        if self.is_deterministic() {
+            return DETERMINISTIC_WORKGROUP_SIZE;
+        }
+        let limits = self.device.limits();
+        let max = limits
+            .max_compute_invocations_per_workgroup
+            .min(limits.max_compute_workgroup_size_x)
+            .min(1024);
+        1 << max.ilog2()
+    }
+
+    /// record all passes of `reduction`, returns the buffer holding the single final partial
+    fn encode_reduce(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        reduction: &Reduction,
+        inputs: &[&PooledBuffer],
+        len: u32,
+        keep: &mut Vec<PooledBuffer>,
+    ) -> Result<PooledBuffer, GpuError> {
+        let wg = self.reduce_workgroup_size();
+        let first_kernel = self.sized_kernel(reduction.first, wg)?;
+        let rest_kernel = self.sized_kernel(reduction.rest, wg)?;
+
+        let mut len = len;
+        let mut prev: Option<PooledBuffer> = None;
+        loop {
+            let groups = len.div_ceil(wg * PER_THREAD).max(1);
+            let params = self.uniform_buffer(&ReduceParams {
+                len,
+                per_thread: PER_THREAD,
+                groups,
+                first: prev.is_none() as u32,
+                identity: reduction.identity,
+                ..Default::default()
+            })?;
+            let out = self.acquire_buffer(
+                groups as u64 * reduction.words * 4,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            )?;
+
+            {
+                let (kernel, pass_inputs) = match &prev {
+                    None => (&first_kernel, inputs.to_vec()),
+                    Some(prev) => (&rest_kernel, vec![prev; reduction.rest_inputs]),
+                };
+                let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: params.binding() }];
+                for (i, input) in pass_inputs.iter().enumerate() {
+                    entries.push(wgpu::BindGroupEntry { binding: i as u32 + 1, resource: input.binding() });
+                }
+                entries.push(wgpu::BindGroupEntry { binding: pass_inputs.len() as u32 + 1, resource: out.binding() });
+
+                self.encode_dispatch(encoder, kernel, 0, self.grid_1d(groups * wg, wg), &entries)?;
+            }
+
+            keep.push(params);
+            keep.extend(prev.take());
+            if groups == 1 {
+                return Ok(out);
+            }
+            prev = Some(out);
+            len = groups;
+        }
+    }
+
+    /// bytes of the final partial of `reduction`
+    async fn reduce(&self, reduction: &Reduction, inputs: &[&PooledBuffer], len: u32) -> Result<Vec<u8>, GpuError> {
+        let mut keep = Vec::new();
+        let out = self
+            .scoped(|| {
+                let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                let out = self.encode_reduce(&mut encoder, reduction, inputs, len, &mut keep)?;
+                self.queue.submit(Some(encoder.finish()));
+                Ok(out)
+            })
+            .await?;
+        drop(keep);
+
+        self.read_back_bytes(&out, reduction.words * 4).await
+    }
+
+    async fn reduce_f32(&self, reduction: &Reduction, id: u64) -> Result<(f32, u32), GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let len = element_count(&shape)?;
+        let bytes = self.reduce(reduction, &[&*buffer], len).await?;
+        Ok((f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]), len))
+    }
+
+    pub async fn tensor_sum(&self, id: u64) -> Result<f32, GpuError> {
+        Ok(self.reduce_f32(&SUM, id).await?.0)
+    }
+
+    pub async fn tensor_mean(&self, id: u64) -> Result<f32, GpuError> {
+        let (sum, len) = self.reduce_f32(&SUM, id).await?;
+        check_not_empty("mean", len as usize)?;
+        Ok(sum / len as f32)
+    }
+
+    pub async fn tensor_min(&self, id: u64) -> Result<f32, GpuError> {
+        let (min, len) = self.reduce_f32(&MIN, id).await?;
+        check_not_empty("min", len as usize)?;
+        Ok(min)
+    }
+
+    pub async fn tensor_max(&self, id: u64) -> Result<f32, GpuError> {
+        let (max, len) = self.reduce_f32(&MAX, id).await?;
+        check_not_empty("max", len as usize)?;
+        Ok(max)
+    }
+
+    /// sum of the elementwise product, the shapes only need the same number of values
+    pub async fn tensor_dot(&self, a: u64, b: u64) -> Result<f32, GpuError> {
+        let (buffer_a, shape_a) = self.tensor(a, DType::F32)?;
+        let (buffer_b, shape_b) = self.tensor(b, DType::F32)?;
+        let len = element_count(&shape_a)?;
+        if len != element_count(&shape_b)? {
+            return Err(GpuError::ShapeMismatch(format!("{:?} vs {:?}", shape_a, shape_b)));
+        }
+
+        let bytes = self.reduce(&DOT, &[&*buffer_a, &*buffer_b], len).await?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// flat index of the largest value, the first one on ties, NaN is skipped
+    pub async fn tensor_argmax(&self, id: u64) -> Result<u32, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let len = element_count(&shape)?;
+        check_not_empty("argmax", len as usize)?;
+
+        let bytes = self.reduce(&ARGMAX, &[&*buffer, &*buffer], len).await?;
+        match u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) {
+            u32::MAX => Err(GpuError::InvalidArgument("argmax of a tensor holding only NaN".into())),
+            index => Ok(index),
+        }
+    }
+
+    /// block scan of `x` into `out`, the block totals are scanned recursively
+    fn encode_scan(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        x: &PooledBuffer,
+        out: &PooledBuffer,
+        len: u32,
+        exclusive: bool,
+        keep: &mut Vec<PooledBuffer>,
+    ) -> Result<(), GpuError> {
+        let wg = self.reduce_workgroup_size();
+        let groups = len.div_ceil(wg).max(1);
+        let params = self.uniform_buffer(&ScanParams { len, exclusive: exclusive as u32, groups, _pad: 0 })?;
+        let sums = self.acquire_buffer(groups as u64 * 4, wgpu::BufferUsages::STORAGE)?;
+
+        self.encode_dispatch(encoder, &self.sized_kernel("scan_block", wg)?, 0, self.grid_1d(groups * wg, wg), &[
+            wgpu::BindGroupEntry { binding: 0, resource: params.binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: x.binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: out.binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: sums.binding() },
+        ])?;
+
+        if groups > 1 {
+            let offsets = self.acquire_buffer(groups as u64 * 4, wgpu::BufferUsages::STORAGE)?;
+            self.encode_scan(encoder, &sums, &offsets, groups, true, keep)?;
+
+            self.encode_dispatch(encoder, &self.sized_kernel("scan_add", wg)?, 0, self.grid_1d(groups * wg, wg), &[
+                wgpu::BindGroupEntry { binding: 0, resource: params.binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: offsets.binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: out.binding() },
+            ])?;
+            keep.push(offsets);
+        }
+
+        keep.push(params);
+        keep.push(sums);
+        Ok(())
+    }
+
+    /// prefix sum over all values in memory order, the result keeps the shape
+    pub async fn tensor_scan(&self, id: u64, exclusive: bool) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::F32)?;
+        let len = element_count(&shape)?;
+        if len == 0 {
+            return self.tensor_upload(&[], shape);
+        }
+
+        let result = self.alloc_tensor(&shape, DType::F32)?;
+        let mut keep = Vec::new();
+        self.scoped(|| {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.encode_scan(&mut encoder, &buffer, &result, len, exclusive, &mut keep)?;
+            self.queue.submit(Some(encoder.finish()));
+            Ok(())
+        })
+        .await?;
+        drop(keep);
+
+        Ok(self.tensors.lock().unwrap().insert(result, shape, DType::F32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_sum_of_integers_is_exact() {
+        // several blocks and a ragged tail, every partial sum fits in an f32 exactly
+        for len in [0usize, 1, 5, 1024, 1025, 3000, 300_000] {
+            let values: Vec<f32> = (0..len).map(|i| (i % 7) as f32).collect();
+            let naive: f64 = values.iter().map(|v| *v as f64).sum();
+            assert_eq!(tree_sum(values) as f64, naive, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn tree_sum_is_close_to_the_naive_sum() {
+        let values: Vec<f32> = (0..10_000).map(|i| ((i * 7919) % 1000) as f32 / 997.0 - 0.5).collect();
+        let naive: f64 = values.iter().map(|v| *v as f64).sum();
+        assert!((tree_sum(values) as f64 - naive).abs() < 1e-2, "naive {}", naive);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+/// parse and validate with naga so a bad kernel is reported with line info
+/// instead of surfacing later as an opaque device error
+pub fn validate_wgsl(source: &str) -> Result<(naga::Module, naga::valid::ModuleInfo), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| e.emit_to_string(source))?;
+
+    Ok((module, info))
+}
+
+/// layout entries per bind group of the resources `entry_point` actually uses,
+/// index i of the result is group i, unused groups in between are empty
+pub fn reflect_bind_groups(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    entry_point: &str,
+) -> Result<Vec<Vec<wgpu::BindGroupLayoutEntry>>, String> {
+    let index = module
+        .entry_points
+        .iter()
+        .position(|ep| ep.name == entry_point && ep.stage == naga::ShaderStage::Compute)
+        .ok_or_else(|| format!("no compute entry point {}", entry_point))?;
+    let ep_info = info.get_entry_point(index);
+
+    let mut groups: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+
+    for (handle, var) in module.global_variables.iter() {
+        if ep_info[handle].is_empty() {
+            continue;
+        }
+        let binding = match var.binding.as_ref() {
+            Some(binding) => binding,
+            None => continue,
+        };
+
+        let ty = match var.space {
+            naga::AddressSpace::Uniform => wgpu::BufferBindingType::Uniform,
+            naga::AddressSpace::Storage { access } => wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            },
+            other => {
+                return Err(format!(
+                    "{}: binding {}:{} uses unsupported address space {:?}",
+                    entry_point, binding.group, binding.binding, other
+                ))
+            }
+        };
+
+        groups.entry(binding.group).or_default().push(wgpu::BindGroupLayoutEntry {
+            binding: binding.binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+
+    let group_count = groups.keys().next_back().map_or(0, |g| *g as usize + 1);
+    let mut layouts = vec![Vec::new(); group_count];
+    for (group, mut entries) in groups {
+        entries.sort_by_key(|e| e.binding);
+        layouts[group as usize] = entries;
+    }
+
+    Ok(layouts)
+}
+
+/// build the compute pipeline of `entry_point` with a layout derived from `source`
+pub fn create_reflected_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    source: &str,
+    entry_point: &str,
+    cache: Option<&wgpu::PipelineCache>,
+) -> Result<wgpu::ComputePipeline, String> {
+    let (module, info) = validate_wgsl(source)?;
+    let groups = reflect_bind_groups(&module, &info, entry_point)?;
+
+    let bind_group_layouts: Vec<wgpu::BindGroupLayout> = groups
+        .iter()
+        .enumerate()
+        .map(|(i, entries)| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&format!("{}_bgl_{}", entry_point, i)),
+                entries,
+            })
+        })
+        .collect();
+    let bind_group_layout_refs: Vec<&wgpu::BindGroupLayout> = bind_group_layouts.iter().collect();
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{}_layout", entry_point)),
+        bind_group_layouts: &bind_group_layout_refs,
+        push_constant_ranges: &[],
+    });
+
+    Ok(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&pipeline_layout),
+        module: shader_module,
+        entry_point: Some(entry_point),
+        cache,
+        compilation_options: Default::default(),
+    }))
+}
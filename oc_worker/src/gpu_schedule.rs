@@ -0,0 +1,92 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::compute_backend::Backend;
+use crate::gpu_init::GpuManager;
+
+thread_local! {
+    /// every opened device with the number of runs on it
+    static DEVICES: RefCell<Vec<(GpuManager, Rc<Cell<u32>>)>> = const { RefCell::new(Vec::new()) };
+    /// backend of the run being polled right now, see `OnDevice`
+    static RUN_BACKEND: RefCell<Option<Backend>> = const { RefCell::new(None) };
+}
+
+pub fn set_devices(gpus: &[GpuManager]) {
+    DEVICES.with(|devices| {
+        *devices.borrow_mut() = gpus.iter().map(|gpu| (gpu.clone(), Rc::new(Cell::new(0)))).collect();
+    });
+}
+
+/// put `gpu` in the place of the device at `index`, runs holding a lease keep the old one
+pub fn replace_device(index: usize, gpu: GpuManager) {
+    DEVICES.with(|devices| {
+        if let Some(device) = devices.borrow_mut().get_mut(index) {
+            device.0 = gpu;
+        }
+    });
+}
+
+/// all opened devices, for setup that has to reach each of them
+pub fn devices() -> Vec<GpuManager> {
+    DEVICES.with(|devices| devices.borrow().iter().map(|(gpu, _)| gpu.clone()).collect())
+}
+
+pub(crate) fn run_backend() -> Option<Backend> {
+    RUN_BACKEND.with(|backend| backend.borrow().clone())
+}
+
+/// a device picked for one run, counted as busy until dropped
+pub struct DeviceLease {
+    gpu: GpuManager,
+    running: Rc<Cell<u32>>,
+}
+
+/// the device with the fewest runs, `None` unless several devices are open
+pub fn lease_device() -> Option<DeviceLease> {
+    DEVICES.with(|devices| {
+        let devices = devices.borrow();
+        if devices.len() < 2 {
+            return None;
+        }
+        let (gpu, running) = devices.iter().min_by_key(|(_, running)| running.get())?;
+        running.set(running.get() + 1);
+        Some(DeviceLease { gpu: gpu.clone(), running: running.clone() })
+    })
+}
+
+impl DeviceLease {
+    pub fn gpu(&self) -> &GpuManager {
+        &self.gpu
+    }
+
+    /// `f` with `get_backend` answering this device whenever it is polled,
+    /// so runs interleaving on the thread each keep their own
+    pub fn run<F: Future>(&self, f: F) -> OnDevice<F> {
+        OnDevice { backend: Backend::Gpu(self.gpu.clone()), inner: Box::pin(f) }
+    }
+}
+
+impl Drop for DeviceLease {
+    fn drop(&mut self) {
+        self.running.set(self.running.get() - 1);
+    }
+}
+
+pub struct OnDevice<F> {
+    backend: Backend,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for OnDevice<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let previous = RUN_BACKEND.with(|backend| backend.replace(Some(self.backend.clone())));
+        let poll = self.inner.as_mut().poll(cx);
+        RUN_BACKEND.with(|backend| *backend.borrow_mut() = previous);
+        poll
+    }
+}
@@ -1,61 +1,106 @@
-pub const SHADER_SOURCE: &str = r#"
-    struct SingleU32 {
-	    value: u32,
-	};
-
-	@group(0) @binding(0)
-	var<storage, read> a_t: SingleU32;
+/// elementwise family, every entry point runs 256 invocations per workgroup on a grid folded
+/// into y and z past the per dimension limit, `len` guards the tail and pool padding
+pub const ELEMENTWISE_SOURCE: &str = r#"
+    struct ElementwiseParams {
+        len: u32,
+        _pad: u32,
+        alpha: f32,
+        beta: f32,
+    };
 
-	@group(0) @binding(1)
-	var<storage, read> b_t: SingleU32;
+    @group(0) @binding(0)
+    var<uniform> params: ElementwiseParams;
 
-	@group(0) @binding(2)
-	var<storage, read_write> result_t: SingleU32;
+    @group(0) @binding(1)
+    var<storage, read> a: array<T>;
 
-	@compute @workgroup_size(1)
-	fn add_u32(@builtin(global_invocation_id) id: vec3<u32>) {
-	    result_t.value = a_t.value + b_t.value;
-	}
+    @group(0) @binding(2)
+    var<storage, read> b: array<T>;
 
     @group(0) @binding(3)
-    var<storage, read> a: array<f32>;
+    var<storage, read> c: array<T>;
 
     @group(0) @binding(4)
-    var<storage, read> b: array<f32>;
-
-    @group(0) @binding(5)
-    var<storage, read_write> result: array<f32>;
+    var<storage, read_write> result: array<T>;
 
+    fn flat_index(gid: vec3<u32>, nwg: vec3<u32>) -> u32 {
+        return gid.x + (gid.y + gid.z * nwg.y) * nwg.x * 256u;
+    }
 
-    @compute @workgroup_size(1)
-    fn add(@builtin(global_invocation_id) id: vec3<u32>) {
-        let i = id.x;
+    @compute @workgroup_size(256)
+    fn add(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
         result[i] = a[i] + b[i];
     }
 
+    @compute @workgroup_size(256)
+    fn sub(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = a[i] - b[i];
+    }
+
+    @compute @workgroup_size(256)
+    fn mul(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = a[i] * b[i];
+    }
+
+    @compute @workgroup_size(256)
+    fn div(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = a[i] / b[i];
+    }
+
+    @compute @workgroup_size(256)
+    fn mul_add(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = a[i] * b[i] + c[i];
+    }
+
+    @compute @workgroup_size(256)
+    fn scale(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = a[i] * T(params.alpha) + T(params.beta);
+    }
+
+    @compute @workgroup_size(256)
+    fn clamp_range(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.len) { return; }
+        result[i] = clamp(a[i], T(params.alpha), T(params.beta));
+    }
+"#;
+
+pub const MATRIX_MULTIPLY_SOURCE: &str = r#"
     struct MatrixHeader {
         long: u32,
         width: u32,
     };
 
-    @group(0) @binding(6)
+    @group(0) @binding(0)
     var<storage, read> a_header: MatrixHeader;
 
-    @group(0) @binding(7)
+    @group(0) @binding(1)
     var<storage, read> b_header: MatrixHeader;
 
-    @group(0) @binding(8)
-    var<storage, read> a_data: array<f32>;
+    @group(0) @binding(2)
+    var<storage, read> a_data: array<T>;
 
-    @group(0) @binding(9)
-    var<storage, read> b_data: array<f32>;
+    @group(0) @binding(3)
+    var<storage, read> b_data: array<T>;
 
-    @group(0) @binding(10)
-    var<storage, read_write> matrix_result: array<f32>;
+    @group(0) @binding(4)
+    var<storage, read_write> matrix_result: array<T>;
 
     const TILE_SIZE: u32 = 16;
-    var<workgroup> tileA: array<array<f32, TILE_SIZE>, TILE_SIZE>;
-    var<workgroup> tileB: array<array<f32, TILE_SIZE>, TILE_SIZE>;
+    var<workgroup> tileA: array<array<T, TILE_SIZE>, TILE_SIZE>;
+    var<workgroup> tileB: array<array<T, TILE_SIZE>, TILE_SIZE>;
 
     @compute @workgroup_size(TILE_SIZE, TILE_SIZE)
     fn matrix_multiply(@builtin(global_invocation_id) gid: vec3<u32>,
@@ -70,7 +115,7 @@ pub const SHADER_SOURCE: &str = r#"
         let k = a_header.width;
         let n = b_header.width;
 
-        var sum: f32 = 0.0;
+        var sum: T = T(0);
 
         for (var t: u32 = 0u; t < (k + TILE_SIZE - 1u) / TILE_SIZE; t = t + 1u) {
             let tiled_col = t * TILE_SIZE + local_col;
@@ -79,13 +124,13 @@ pub const SHADER_SOURCE: &str = r#"
             if (tiled_col < k && row < m) {
                 tileA[local_row][local_col] = a_data[row * k + tiled_col];
             } else {
-                tileA[local_row][local_col] = 0.0;
+                tileA[local_row][local_col] = T(0);
             }
 
             if (tiled_row < k && col < n) {
                 tileB[local_row][local_col] = b_data[tiled_row * n + col];
             } else {
-                tileB[local_row][local_col] = 0.0;
+                tileB[local_row][local_col] = T(0);
             }
 
             workgroupBarrier();
@@ -101,30 +146,36 @@ pub const SHADER_SOURCE: &str = r#"
             matrix_result[row * n + col] = sum;
         }
     }
+"#;
 
-    //vec muti matrix
+//vec muti matrix
+pub const VECTOR_MATRIX_MULTIPLY_SOURCE: &str = r#"
+    struct MatrixHeader {
+        long: u32,
+        width: u32,
+    };
 
-    @group(0) @binding(11)
-    var<storage, read> vmm_vector: array<f32>;
+    @group(0) @binding(0)
+    var<storage, read> vmm_vector: array<T>;
 
-    @group(0) @binding(12)
+    @group(0) @binding(1)
     var<storage, read> vmm_matrix_header: MatrixHeader;
 
-    @group(0) @binding(13)
-    var<storage, read> vmm_matrix: array<f32>;
+    @group(0) @binding(2)
+    var<storage, read> vmm_matrix: array<T>;
 
-    @group(0) @binding(14)
-    var<storage, read_write> vmm_result: array<f32>;
+    @group(0) @binding(3)
+    var<storage, read_write> vmm_result: array<T>;
 
     @compute @workgroup_size(64)
-    fn vector_matrix_multiply(@builtin(global_invocation_id) gid: vec3<u32>) {
-        let col = gid.x;
+    fn vector_matrix_multiply(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let col = gid.x + gid.y * nwg.x * 64u;
 
         if (col >= vmm_matrix_header.width) {
             return;
         }
 
-        var sum: f32 = 0.0;
+        var sum: T = T(0);
         for (var row: u32 = 0u; row < vmm_matrix_header.long; row = row + 1u) {
             let mat_val = vmm_matrix[col + row * vmm_matrix_header.width];
             let vec_val = vmm_vector[row];
@@ -133,4 +184,918 @@ pub const SHADER_SOURCE: &str = r#"
 
         vmm_result[col] = sum;
     }
-"#;
\ No newline at end of file
+"#;
+
+// u8 data is packed four to a u32, little endian like the upload
+pub const DEQUANTIZE_U8_SOURCE: &str = r#"
+    struct DequantizeParams {
+        len: u32,
+        scale: f32,
+        zero_point: f32,
+        _pad: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: DequantizeParams;
+
+    @group(0) @binding(1)
+    var<storage, read> packed: array<u32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> dequantized: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn dequantize_u8(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = gid.x + gid.y * nwg.x * 64u;
+        if (i >= params.len) {
+            return;
+        }
+
+        let byte = extractBits(packed[i / 4u], (i % 4u) * 8u, 8u);
+        dequantized[i] = (f32(byte) - params.zero_point) * params.scale;
+    }
+"#;
+
+// shared by the nn kernels, unused fields are 0, flat_index spans the 2d grid of grid_1d
+//   rows/cols: leading dims flattened x last dim, inner: seq_k for attention,
+//   flag: causal mask / position offset, eps, scale, base: rope frequency base,
+//   out_cols: value width for attention
+const NN_PARAMS: &str = r#"
+    struct NnParams {
+        rows: u32,
+        cols: u32,
+        inner: u32,
+        flag: u32,
+        eps: f32,
+        scale: f32,
+        base: f32,
+        out_cols: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: NnParams;
+
+    fn flat_index(gid: vec3<u32>, nwg: vec3<u32>) -> u32 {
+        return gid.x + gid.y * nwg.x * 64u;
+    }
+"#;
+
+// numerically stable, one invocation per row
+pub const SOFTMAX_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> y: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn softmax(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let row = flat_index(gid, nwg);
+        if (row >= params.rows) {
+            return;
+        }
+        let start = row * params.cols;
+
+        var m: f32 = x[start];
+        for (var j: u32 = 1u; j < params.cols; j = j + 1u) {
+            m = max(m, x[start + j]);
+        }
+
+        var sum: f32 = 0.0;
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            let e = exp(x[start + j] - m);
+            y[start + j] = e;
+            sum = sum + e;
+        }
+
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            y[start + j] = y[start + j] / sum;
+        }
+    }
+"#;
+
+pub const LAYER_NORM_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> gamma: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read> beta: array<f32>;
+
+    @group(0) @binding(4)
+    var<storage, read_write> y: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn layer_norm(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let row = flat_index(gid, nwg);
+        if (row >= params.rows) {
+            return;
+        }
+        let start = row * params.cols;
+        let n = f32(params.cols);
+
+        var mean: f32 = 0.0;
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            mean = mean + x[start + j];
+        }
+        mean = mean / n;
+
+        var variance: f32 = 0.0;
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            let d = x[start + j] - mean;
+            variance = variance + d * d;
+        }
+        let inv = 1.0 / sqrt(variance / n + params.eps);
+
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            y[start + j] = (x[start + j] - mean) * inv * gamma[j] + beta[j];
+        }
+    }
+"#;
+
+pub const RMS_NORM_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> gamma: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read_write> y: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn rms_norm(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let row = flat_index(gid, nwg);
+        if (row >= params.rows) {
+            return;
+        }
+        let start = row * params.cols;
+
+        var sum_sq: f32 = 0.0;
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            sum_sq = sum_sq + x[start + j] * x[start + j];
+        }
+        let inv = 1.0 / sqrt(sum_sq / f32(params.cols) + params.eps);
+
+        for (var j: u32 = 0u; j < params.cols; j = j + 1u) {
+            y[start + j] = x[start + j] * inv * gamma[j];
+        }
+    }
+"#;
+
+// gelu uses the tanh approximation
+pub const ACTIVATION_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> y: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn gelu(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.rows * params.cols) {
+            return;
+        }
+        let v = x[i];
+        y[i] = 0.5 * v * (1.0 + tanh(0.7978845608 * (v + 0.044715 * v * v * v)));
+    }
+
+    @compute @workgroup_size(64)
+    fn silu(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = flat_index(gid, nwg);
+        if (i >= params.rows * params.cols) {
+            return;
+        }
+        let v = x[i];
+        y[i] = v / (1.0 + exp(-v));
+    }
+"#;
+
+// rotates interleaved pairs (x[2i], x[2i+1]) of each row by (row + flag) * base^(-2i/cols)
+pub const ROPE_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> y: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn rope(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let half_cols = params.cols / 2u;
+        let pair = flat_index(gid, nwg);
+        if (pair >= params.rows * half_cols) {
+            return;
+        }
+        let row = pair / half_cols;
+        let i = pair % half_cols;
+
+        let pos = f32(row + params.flag);
+        let theta = pos * pow(params.base, -2.0 * f32(i) / f32(params.cols));
+        let c = cos(theta);
+        let s = sin(theta);
+
+        let at = row * params.cols + 2u * i;
+        let x0 = x[at];
+        let x1 = x[at + 1u];
+        y[at] = x0 * c - x1 * s;
+        y[at + 1u] = x0 * s + x1 * c;
+    }
+"#;
+
+// q: rows x cols, k: inner x cols, v: inner x out_cols, one invocation per query row with an
+// online softmax so scores are never stored, keys are visited in order for reproducible sums
+pub const ATTENTION_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> q: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> k: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read> v: array<f32>;
+
+    @group(0) @binding(4)
+    var<storage, read_write> out: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn attention(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let row = flat_index(gid, nwg);
+        if (row >= params.rows) {
+            return;
+        }
+        let d = params.cols;
+        let out_cols = params.out_cols;
+
+        // with a causal mask query `row` sees keys up to its own position, aligned to the end of k
+        var last = params.inner;
+        if (params.flag != 0u) {
+            last = min(params.inner, row + 1u + params.inner - min(params.inner, params.rows));
+        }
+
+        for (var c: u32 = 0u; c < out_cols; c = c + 1u) {
+            out[row * out_cols + c] = 0.0;
+        }
+
+        var m: f32 = -3.4e38;
+        var l: f32 = 0.0;
+        for (var j: u32 = 0u; j < last; j = j + 1u) {
+            var score: f32 = 0.0;
+            for (var t: u32 = 0u; t < d; t = t + 1u) {
+                score = score + q[row * d + t] * k[j * d + t];
+            }
+            score = score * params.scale;
+
+            let m_new = max(m, score);
+            let correction = exp(m - m_new);
+            let p = exp(score - m_new);
+            l = l * correction + p;
+            for (var c: u32 = 0u; c < out_cols; c = c + 1u) {
+                let at = row * out_cols + c;
+                out[at] = out[at] * correction + p * v[j * out_cols + c];
+            }
+            m = m_new;
+        }
+
+        if (l > 0.0) {
+            for (var c: u32 = 0u; c < out_cols; c = c + 1u) {
+                out[row * out_cols + c] = out[row * out_cols + c] / l;
+            }
+        }
+    }
+"#;
+
+/// nn kernel entry point -> source without the `NnParams` header, see `nn_source`
+pub const NN_KERNELS: &[(&str, &str)] = &[
+    ("softmax", SOFTMAX_SOURCE),
+    ("layer_norm", LAYER_NORM_SOURCE),
+    ("rms_norm", RMS_NORM_SOURCE),
+    ("gelu", ACTIVATION_SOURCE),
+    ("silu", ACTIVATION_SOURCE),
+    ("rope", ROPE_SOURCE),
+    ("attention", ATTENTION_SOURCE),
+];
+
+pub fn nn_source(body: &str) -> String {
+    format!("{}{}", NN_PARAMS, body)
+}
+
+// reductions fold `per_thread` contiguous values per invocation, then halve in workgroup memory,
+// each pass leaves one value per workgroup until a single one is left
+const REDUCE_PARAMS: &str = r#"
+    struct ReduceParams {
+        len: u32,
+        per_thread: u32,
+        groups: u32,
+        first: u32,
+        identity: f32,
+        _pad0: u32,
+        _pad1: u32,
+        _pad2: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: ReduceParams;
+"#;
+
+pub const REDUCE_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> partial: array<f32>;
+
+    var<workgroup> s: array<f32, WG>;
+
+    // 0 sum, 1 min, 2 max
+    fn combine(op: u32, a: f32, b: f32) -> f32 {
+        switch op {
+            case 1u: { return min(a, b); }
+            case 2u: { return max(a, b); }
+            default: { return a + b; }
+        }
+    }
+
+    fn reduce(op: u32, lid: u32, group: u32) {
+        let start = (group * WG + lid) * params.per_thread;
+        var acc: f32 = params.identity;
+        for (var k: u32 = 0u; k < params.per_thread; k = k + 1u) {
+            if (start + k < params.len) {
+                acc = combine(op, acc, x[start + k]);
+            }
+        }
+        s[lid] = acc;
+        workgroupBarrier();
+
+        for (var stride: u32 = WG / 2u; stride > 0u; stride = stride / 2u) {
+            if (lid < stride) {
+                s[lid] = combine(op, s[lid], s[lid + stride]);
+            }
+            workgroupBarrier();
+        }
+
+        if (lid == 0u) {
+            partial[group] = s[0];
+        }
+    }
+
+    @compute @workgroup_size(WG)
+    fn reduce_sum(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+        reduce(0u, lid, group);
+    }
+
+    @compute @workgroup_size(WG)
+    fn reduce_min(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+        reduce(1u, lid, group);
+    }
+
+    @compute @workgroup_size(WG)
+    fn reduce_max(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+        reduce(2u, lid, group);
+    }
+"#;
+
+// first pass of a dot product, the partial sums go on through reduce_sum
+pub const REDUCE_DOT_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> y: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read_write> partial: array<f32>;
+
+    var<workgroup> s: array<f32, WG>;
+
+    @compute @workgroup_size(WG)
+    fn reduce_dot(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+
+        let start = (group * WG + lid) * params.per_thread;
+        var acc: f32 = 0.0;
+        for (var k: u32 = 0u; k < params.per_thread; k = k + 1u) {
+            if (start + k < params.len) {
+                acc = acc + x[start + k] * y[start + k];
+            }
+        }
+        s[lid] = acc;
+        workgroupBarrier();
+
+        for (var stride: u32 = WG / 2u; stride > 0u; stride = stride / 2u) {
+            if (lid < stride) {
+                s[lid] = s[lid] + s[lid + stride];
+            }
+            workgroupBarrier();
+        }
+
+        if (lid == 0u) {
+            partial[group] = s[0];
+        }
+    }
+"#;
+
+// (value bits, index) pairs, the first pass reads `x` and later ones the pairs of the previous pass,
+// ties go to the lower index and NaN never wins
+pub const REDUCE_ARGMAX_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> pairs_in: array<u32>;
+
+    @group(0) @binding(3)
+    var<storage, read_write> pairs_out: array<u32>;
+
+    var<workgroup> sv: array<f32, WG>;
+    var<workgroup> si: array<u32, WG>;
+
+    fn better(v: f32, i: u32, w: f32, j: u32) -> bool {
+        return v > w || (v == w && i < j);
+    }
+
+    @compute @workgroup_size(WG)
+    fn reduce_argmax(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+
+        let start = (group * WG + lid) * params.per_thread;
+        var best_v: f32 = params.identity;
+        var best_i: u32 = 0xffffffffu;
+        for (var k: u32 = 0u; k < params.per_thread; k = k + 1u) {
+            let i = start + k;
+            if (i < params.len) {
+                var v: f32;
+                var idx: u32;
+                if (params.first != 0u) {
+                    v = x[i];
+                    idx = i;
+                } else {
+                    v = bitcast<f32>(pairs_in[2u * i]);
+                    idx = pairs_in[2u * i + 1u];
+                }
+                if (better(v, idx, best_v, best_i)) {
+                    best_v = v;
+                    best_i = idx;
+                }
+            }
+        }
+        sv[lid] = best_v;
+        si[lid] = best_i;
+        workgroupBarrier();
+
+        for (var stride: u32 = WG / 2u; stride > 0u; stride = stride / 2u) {
+            if (lid < stride && better(sv[lid + stride], si[lid + stride], sv[lid], si[lid])) {
+                sv[lid] = sv[lid + stride];
+                si[lid] = si[lid + stride];
+            }
+            workgroupBarrier();
+        }
+
+        if (lid == 0u) {
+            pairs_out[2u * group] = bitcast<u32>(sv[0]);
+            pairs_out[2u * group + 1u] = si[0];
+        }
+    }
+"#;
+
+const SCAN_PARAMS: &str = r#"
+    struct ScanParams {
+        len: u32,
+        exclusive: u32,
+        groups: u32,
+        _pad: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: ScanParams;
+"#;
+
+// scans one workgroup sized block and stores its total, the block totals are scanned
+// exclusively and added back by scan_add
+pub const SCAN_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> out: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read_write> block_sums: array<f32>;
+
+    var<workgroup> s: array<f32, WG>;
+
+    @compute @workgroup_size(WG)
+    fn scan_block(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        if (group >= params.groups) {
+            return;
+        }
+
+        let i = group * WG + lid;
+        var v: f32 = 0.0;
+        if (i < params.len) {
+            v = x[i];
+        }
+        s[lid] = v;
+        workgroupBarrier();
+
+        for (var offset: u32 = 1u; offset < WG; offset = offset * 2u) {
+            var t: f32 = 0.0;
+            if (lid >= offset) {
+                t = s[lid - offset];
+            }
+            workgroupBarrier();
+            s[lid] = s[lid] + t;
+            workgroupBarrier();
+        }
+
+        if (i < params.len) {
+            if (params.exclusive == 0u) {
+                out[i] = s[lid];
+            } else if (lid == 0u) {
+                out[i] = 0.0;
+            } else {
+                out[i] = s[lid - 1u];
+            }
+        }
+        if (lid == WG - 1u) {
+            block_sums[group] = s[lid];
+        }
+    }
+"#;
+
+pub const SCAN_ADD_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> offsets: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> out: array<f32>;
+
+    @compute @workgroup_size(WG)
+    fn scan_add(@builtin(local_invocation_index) lid: u32, @builtin(workgroup_id) wid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let group = wid.x + wid.y * nwg.x;
+        let i = group * WG + lid;
+        if (group < params.groups && i < params.len) {
+            out[i] = out[i] + offsets[group];
+        }
+    }
+"#;
+
+/// entry point -> (params header, body) of kernels using the workgroup size `WG`,
+/// compiled per size on first use, see `sized_source`
+pub const SIZED_KERNELS: &[(&str, (&str, &str))] = &[
+    ("reduce_sum", (REDUCE_PARAMS, REDUCE_SOURCE)),
+    ("reduce_min", (REDUCE_PARAMS, REDUCE_SOURCE)),
+    ("reduce_max", (REDUCE_PARAMS, REDUCE_SOURCE)),
+    ("reduce_dot", (REDUCE_PARAMS, REDUCE_DOT_SOURCE)),
+    ("reduce_argmax", (REDUCE_PARAMS, REDUCE_ARGMAX_SOURCE)),
+    ("scan_block", (SCAN_PARAMS, SCAN_SOURCE)),
+    ("scan_add", (SCAN_PARAMS, SCAN_ADD_SOURCE)),
+];
+
+pub fn sized_source((header, body): (&str, &str), workgroup_size: u32) -> String {
+    format!("const WG: u32 = {}u;\n{}{}", workgroup_size, header, body)
+}
+
+// C = alpha * op(A) * op(B) + beta * C + bias for matrix gid.z of a batch, row major with
+// leading dims and batch strides like sgemm, tiled like matrix_multiply
+pub const GEMM_SOURCE: &str = r#"
+    struct GemmParams {
+        m: u32,
+        n: u32,
+        k: u32,
+        batch: u32,
+        lda: u32,
+        ldb: u32,
+        ldc: u32,
+        trans_a: u32,
+        stride_a: u32,
+        stride_b: u32,
+        stride_c: u32,
+        trans_b: u32,
+        alpha: f32,
+        beta: f32,
+        has_c: u32,
+        has_bias: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: GemmParams;
+
+    @group(0) @binding(1)
+    var<storage, read> a: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> b: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read> c_in: array<f32>;
+
+    @group(0) @binding(4)
+    var<storage, read> bias: array<f32>;
+
+    @group(0) @binding(5)
+    var<storage, read_write> c_out: array<f32>;
+
+    const TILE_SIZE: u32 = 16;
+    var<workgroup> tileA: array<array<f32, TILE_SIZE>, TILE_SIZE>;
+    var<workgroup> tileB: array<array<f32, TILE_SIZE>, TILE_SIZE>;
+
+    // op(A)[row][col], row < m, col < k
+    fn load_a(batch: u32, row: u32, col: u32) -> f32 {
+        let base = batch * params.stride_a;
+        if (params.trans_a != 0u) {
+            return a[base + col * params.lda + row];
+        }
+        return a[base + row * params.lda + col];
+    }
+
+    // op(B)[row][col], row < k, col < n
+    fn load_b(batch: u32, row: u32, col: u32) -> f32 {
+        let base = batch * params.stride_b;
+        if (params.trans_b != 0u) {
+            return b[base + col * params.ldb + row];
+        }
+        return b[base + row * params.ldb + col];
+    }
+
+    @compute @workgroup_size(TILE_SIZE, TILE_SIZE)
+    fn gemm(@builtin(global_invocation_id) gid: vec3<u32>,
+            @builtin(local_invocation_id) lid: vec3<u32>) {
+        let row = gid.y;
+        let col = gid.x;
+        let batch = gid.z;
+
+        var sum: f32 = 0.0;
+
+        for (var t: u32 = 0u; t < (params.k + TILE_SIZE - 1u) / TILE_SIZE; t = t + 1u) {
+            let tiled_col = t * TILE_SIZE + lid.x;
+            let tiled_row = t * TILE_SIZE + lid.y;
+
+            if (tiled_col < params.k && row < params.m) {
+                tileA[lid.y][lid.x] = load_a(batch, row, tiled_col);
+            } else {
+                tileA[lid.y][lid.x] = 0.0;
+            }
+
+            if (tiled_row < params.k && col < params.n) {
+                tileB[lid.y][lid.x] = load_b(batch, tiled_row, col);
+            } else {
+                tileB[lid.y][lid.x] = 0.0;
+            }
+
+            workgroupBarrier();
+
+            for (var i: u32 = 0u; i < TILE_SIZE; i = i + 1u) {
+                sum = sum + tileA[lid.y][i] * tileB[i][lid.x];
+            }
+
+            workgroupBarrier();
+        }
+
+        if (row < params.m && col < params.n) {
+            let at = batch * params.stride_c + row * params.ldc + col;
+            var value = params.alpha * sum;
+            if (params.has_c != 0u) {
+                value = value + params.beta * c_in[at];
+            }
+            if (params.has_bias != 0u) {
+                value = value + bias[col];
+            }
+            c_out[at] = value;
+        }
+    }
+"#;
+
+// NCHW tensors, one invocation per output value over a 2d grid of 64 wide workgroups,
+// flag: has_bias for conv2d, average instead of max for pool2d
+const CONV_PARAMS: &str = r#"
+    struct ConvParams {
+        n: u32,
+        c: u32,
+        h: u32,
+        w: u32,
+        oc: u32,
+        oh: u32,
+        ow: u32,
+        kh: u32,
+        kw: u32,
+        sh: u32,
+        sw: u32,
+        ph: u32,
+        pw: u32,
+        dh: u32,
+        dw: u32,
+        groups: u32,
+        flag: u32,
+        _pad0: u32,
+        _pad1: u32,
+        _pad2: u32,
+    };
+
+    @group(0) @binding(0)
+    var<uniform> params: ConvParams;
+
+    // input coordinate of kernel tap `k` for output `o`, -1 when it falls into the padding
+    fn tap(o: u32, k: u32, stride: u32, dilation: u32, pad: u32, size: u32) -> i32 {
+        let at = i32(o * stride + k * dilation) - i32(pad);
+        if (at < 0 || at >= i32(size)) {
+            return -1;
+        }
+        return at;
+    }
+"#;
+
+pub const CONV2D_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read> weight: array<f32>;
+
+    @group(0) @binding(3)
+    var<storage, read> bias: array<f32>;
+
+    @group(0) @binding(4)
+    var<storage, read_write> out: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn conv2d(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = gid.x + gid.y * nwg.x * 64u;
+        if (i >= params.n * params.oc * params.oh * params.ow) {
+            return;
+        }
+        let ox = i % params.ow;
+        let oy = (i / params.ow) % params.oh;
+        let o = (i / (params.ow * params.oh)) % params.oc;
+        let b = i / (params.ow * params.oh * params.oc);
+
+        let cpg = params.c / params.groups;
+        let g = o / (params.oc / params.groups);
+
+        var sum: f32 = 0.0;
+        for (var ci: u32 = 0u; ci < cpg; ci = ci + 1u) {
+            let ic = g * cpg + ci;
+            for (var ky: u32 = 0u; ky < params.kh; ky = ky + 1u) {
+                let iy = tap(oy, ky, params.sh, params.dh, params.ph, params.h);
+                if (iy < 0) {
+                    continue;
+                }
+                for (var kx: u32 = 0u; kx < params.kw; kx = kx + 1u) {
+                    let ix = tap(ox, kx, params.sw, params.dw, params.pw, params.w);
+                    if (ix < 0) {
+                        continue;
+                    }
+                    let x_at = ((b * params.c + ic) * params.h + u32(iy)) * params.w + u32(ix);
+                    let w_at = ((o * cpg + ci) * params.kh + ky) * params.kw + kx;
+                    sum = sum + x[x_at] * weight[w_at];
+                }
+            }
+        }
+
+        if (params.flag != 0u) {
+            sum = sum + bias[o];
+        }
+        out[i] = sum;
+    }
+"#;
+
+// padding never counts towards an average
+pub const POOL2D_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> out: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn pool2d(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = gid.x + gid.y * nwg.x * 64u;
+        if (i >= params.n * params.c * params.oh * params.ow) {
+            return;
+        }
+        let ox = i % params.ow;
+        let oy = (i / params.ow) % params.oh;
+        let plane = i / (params.ow * params.oh);
+
+        var acc: f32 = -3.4028235e38;
+        if (params.flag != 0u) {
+            acc = 0.0;
+        }
+        var count: u32 = 0u;
+        for (var ky: u32 = 0u; ky < params.kh; ky = ky + 1u) {
+            let iy = tap(oy, ky, params.sh, params.dh, params.ph, params.h);
+            if (iy < 0) {
+                continue;
+            }
+            for (var kx: u32 = 0u; kx < params.kw; kx = kx + 1u) {
+                let ix = tap(ox, kx, params.sw, params.dw, params.pw, params.w);
+                if (ix < 0) {
+                    continue;
+                }
+                let v = x[(plane * params.h + u32(iy)) * params.w + u32(ix)];
+                if (params.flag != 0u) {
+                    acc = acc + v;
+                } else {
+                    acc = max(acc, v);
+                }
+                count = count + 1u;
+            }
+        }
+
+        if (params.flag != 0u) {
+            acc = acc / f32(max(count, 1u));
+        }
+        out[i] = acc;
+    }
+"#;
+
+// out[b][(c * kh + ky) * kw + kx][oy * ow + ox], zero where the tap falls into the padding
+pub const IM2COL_SOURCE: &str = r#"
+    @group(0) @binding(1)
+    var<storage, read> x: array<f32>;
+
+    @group(0) @binding(2)
+    var<storage, read_write> out: array<f32>;
+
+    @compute @workgroup_size(64)
+    fn im2col(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+        let i = gid.x + gid.y * nwg.x * 64u;
+        let cols = params.oh * params.ow;
+        let rows = params.c * params.kh * params.kw;
+        if (i >= params.n * rows * cols) {
+            return;
+        }
+        let col = i % cols;
+        let row = (i / cols) % rows;
+        let b = i / (cols * rows);
+
+        let ic = row / (params.kh * params.kw);
+        let ky = (row / params.kw) % params.kh;
+        let kx = row % params.kw;
+        let iy = tap(col / params.ow, ky, params.sh, params.dh, params.ph, params.h);
+        let ix = tap(col % params.ow, kx, params.sw, params.dw, params.pw, params.w);
+
+        var v: f32 = 0.0;
+        if (iy >= 0 && ix >= 0) {
+            v = x[((b * params.c + ic) * params.h + u32(iy)) * params.w + u32(ix)];
+        }
+        out[i] = v;
+    }
+"#;
+
+/// conv kernel entry point -> source without the `ConvParams` header, see `conv_source`
+pub const CONV_KERNELS: &[(&str, &str)] = &[
+    ("conv2d", CONV2D_SOURCE),
+    ("pool2d", POOL2D_SOURCE),
+    ("im2col", IM2COL_SOURCE),
+];
+
+pub fn conv_source(body: &str) -> String {
+    format!("{}{}", CONV_PARAMS, body)
+}
+
+/// entry point -> wgsl source, every kernel numbers its bindings from 0 in group 0
+pub const KERNELS: &[(&str, &str)] = &[
+    ("dequantize_u8", DEQUANTIZE_U8_SOURCE),
+    ("gemm", GEMM_SOURCE),
+];
+
+/// kernels written against an element type `T`, compiled per dtype with `typed_source`
+pub const TYPED_KERNELS: &[(&str, &str)] = &[
+    ("add", ELEMENTWISE_SOURCE),
+    ("sub", ELEMENTWISE_SOURCE),
+    ("mul", ELEMENTWISE_SOURCE),
+    ("div", ELEMENTWISE_SOURCE),
+    ("mul_add", ELEMENTWISE_SOURCE),
+    ("scale", ELEMENTWISE_SOURCE),
+    ("clamp_range", ELEMENTWISE_SOURCE),
+    ("matrix_multiply", MATRIX_MULTIPLY_SOURCE),
+    ("vector_matrix_multiply", VECTOR_MATRIX_MULTIPLY_SOURCE),
+];
+
+/// `template` with `T` bound to `wgsl_type`
+pub fn typed_source(template: &str, wgsl_type: &str) -> String {
+    let enable = if wgsl_type == "f16" { "enable f16;\n" } else { "" };
+    format!("{}alias T = {};\n{}", enable, wgsl_type, template)
+}
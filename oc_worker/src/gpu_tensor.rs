@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::gpu_buffer_pool::PooledBuffer;
+use crate::gpu_dtype::DType;
+use crate::gpu_elementwise::ElementwiseOp;
+use crate::gpu_error::GpuError;
+use crate::gpu_func::MatrixHeader;
+use crate::gpu_gemm::GemmSpec;
+use crate::gpu_init::GpuManager;
+
+/// a gpu resident array, row major when it has two dims
+pub struct GpuTensor {
+    pub buffer: Arc<PooledBuffer>,
+    pub shape: Vec<u32>,
+    pub dtype: DType,
+    refcount: u32,
+}
+
+const HANDLE_TAG_SHIFT: u32 = 40;
+
+static NEXT_HANDLE_TAG: AtomicU64 = AtomicU64::new(1);
+
+/// handles of one store, tagged in their high bits with a number no other store has,
+/// so a handle taken to another device is refused instead of naming an unrelated tensor there
+pub(crate) struct HandleSpace {
+    tag: u64,
+    next: u64,
+}
+
+impl HandleSpace {
+    pub fn new() -> Self {
+        Self { tag: NEXT_HANDLE_TAG.fetch_add(1, Ordering::Relaxed) << HANDLE_TAG_SHIFT, next: 1 }
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.tag | self.next;
+        self.next += 1;
+        id
+    }
+
+    /// the error for a handle this store does not hold
+    pub fn missing(&self, kind: &'static str, id: u64) -> GpuError {
+        if id >> HANDLE_TAG_SHIFT == self.tag >> HANDLE_TAG_SHIFT {
+            GpuError::InvalidHandle { kind, id }
+        } else {
+            GpuError::ForeignHandle { kind, id }
+        }
+    }
+}
+
+/// handle -> tensor, handles are never reused and never valid in another store
+pub struct TensorStore {
+    ids: HandleSpace,
+    tensors: HashMap<u64, GpuTensor>,
+}
+
+impl TensorStore {
+    pub fn new() -> Self {
+        Self { ids: HandleSpace::new(), tensors: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, buffer: PooledBuffer, shape: Vec<u32>, dtype: DType) -> u64 {
+        let id = self.ids.next_id();
+        self.tensors.insert(id, GpuTensor { buffer: Arc::new(buffer), shape, dtype, refcount: 1 });
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Result<&GpuTensor, GpuError> {
+        self.tensors.get(&id).ok_or_else(|| self.ids.missing("tensor", id))
+    }
+
+    pub fn retain(&mut self, id: u64) -> Result<(), GpuError> {
+        let tensor = self.tensors.get_mut(&id).ok_or_else(|| self.ids.missing("tensor", id))?;
+        tensor.refcount += 1;
+        Ok(())
+    }
+
+    /// drop one reference, the buffer goes back to the pool with the last one
+    pub fn release(&mut self, id: u64) -> Result<(), GpuError> {
+        let tensor = self.tensors.get_mut(&id).ok_or_else(|| self.ids.missing("tensor", id))?;
+        tensor.refcount -= 1;
+        if tensor.refcount == 0 {
+            self.tensors.remove(&id);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.tensors.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DequantizeParams {
+    len: u32,
+    scale: f32,
+    zero_point: f32,
+    _pad: u32,
+}
+
+/// number of values in `shape`, shapes come from scripts so the product is checked
+pub(crate) fn element_count(shape: &[u32]) -> Result<u32, GpuError> {
+    shape
+        .iter()
+        .try_fold(1u32, |count, dim| count.checked_mul(*dim))
+        .ok_or_else(|| GpuError::InvalidArgument(format!("shape {:?} has more than {} values", shape, u32::MAX)))
+}
+
+pub(crate) fn matrix_dims(shape: &[u32]) -> Result<(u32, u32), GpuError> {
+    match shape {
+        [long, width] => Ok((*long, *width)),
+        _ => Err(GpuError::ShapeMismatch(format!("expected a 2d tensor, got shape {:?}", shape))),
+    }
+}
+
+impl GpuManager {
+    fn tensor_usage() -> wgpu::BufferUsages {
+        // uniform so runtime kernels can take small parameter tensors as `var<uniform>`
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
+    }
+
+    /// bindings and copies work on whole words, so f16 and u8 tensors are padded to 4 bytes
+    pub(crate) fn alloc_tensor(&self, shape: &[u32], dtype: DType) -> Result<PooledBuffer, GpuError> {
+        let len = element_count(shape)? as u64;
+        self.acquire_buffer((len * dtype.size()).next_multiple_of(4), Self::tensor_usage())
+    }
+
+    /// buffer and shape of a tensor that must hold `dtype`
+    pub fn tensor(&self, id: u64, dtype: DType) -> Result<(Arc<PooledBuffer>, Vec<u32>), GpuError> {
+        let tensors = self.tensors.lock().unwrap();
+        let tensor = tensors.get(id)?;
+        if tensor.dtype != dtype {
+            return Err(GpuError::InvalidArgument(format!(
+                "tensor {} is {}, expected {}",
+                id,
+                tensor.dtype.name(),
+                dtype.name()
+            )));
+        }
+        Ok((tensor.buffer.clone(), tensor.shape.clone()))
+    }
+
+    pub fn tensor_dtype(&self, id: u64) -> Result<DType, GpuError> {
+        Ok(self.tensors.lock().unwrap().get(id)?.dtype)
+    }
+
+    pub fn tensor_upload_bytes(&self, data: &[u8], shape: Vec<u32>, dtype: DType) -> Result<u64, GpuError> {
+        let len = element_count(&shape)? as u64;
+        if len * dtype.size() != data.len() as u64 {
+            return Err(GpuError::ShapeMismatch(format!(
+                "shape {:?} does not match {} bytes of {}",
+                shape,
+                data.len(),
+                dtype.name()
+            )));
+        }
+
+        let buffer = self.alloc_tensor(&shape, dtype)?;
+        if !data.is_empty() {
+            if data.len().is_multiple_of(4) {
+                self.write_timed(&buffer, data);
+            } else {
+                let mut padded = data.to_vec();
+                padded.resize(data.len().next_multiple_of(4), 0);
+                self.write_timed(&buffer, &padded);
+            }
+        }
+
+        Ok(self.tensors.lock().unwrap().insert(buffer, shape, dtype))
+    }
+
+    pub fn tensor_upload(&self, data: &[f32], shape: Vec<u32>) -> Result<u64, GpuError> {
+        self.tensor_upload_bytes(bytemuck::cast_slice(data), shape, DType::F32)
+    }
+
+    /// upload host f32 values converted to `dtype`, e.g. f16 activations or u8 weights
+    pub fn tensor_upload_as(&self, data: &[f32], shape: Vec<u32>, dtype: DType) -> Result<u64, GpuError> {
+        self.tensor_upload_bytes(&dtype.encode(data), shape, dtype)
+    }
+
+    /// zero filled tensor, e.g. the output of a runtime kernel
+    pub fn tensor_zeros(&self, shape: Vec<u32>, dtype: DType) -> Result<u64, GpuError> {
+        let buffer = self.alloc_tensor(&shape, dtype)?;
+        // pooled buffers keep old contents
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.clear_buffer(&buffer, 0, None);
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(self.tensors.lock().unwrap().insert(buffer, shape, dtype))
+    }
+
+    pub async fn tensor_download_bytes(&self, id: u64) -> Result<(Vec<u8>, DType), GpuError> {
+        let (buffer, shape, dtype) = {
+            let tensors = self.tensors.lock().unwrap();
+            let tensor = tensors.get(id)?;
+            (tensor.buffer.clone(), tensor.shape.clone(), tensor.dtype)
+        };
+        let len = element_count(&shape)? as u64;
+        Ok((self.read_back_bytes(&buffer, len * dtype.size()).await?, dtype))
+    }
+
+    /// values of any dtype widened to f32
+    pub async fn tensor_download(&self, id: u64) -> Result<Vec<f32>, GpuError> {
+        let (bytes, dtype) = self.tensor_download_bytes(id).await?;
+        Ok(dtype.decode(&bytes))
+    }
+
+    pub fn tensor_shape(&self, id: u64) -> Result<Vec<u32>, GpuError> {
+        Ok(self.tensors.lock().unwrap().get(id)?.shape.clone())
+    }
+
+    pub fn tensor_retain(&self, id: u64) -> Result<(), GpuError> {
+        self.tensors.lock().unwrap().retain(id)
+    }
+
+    pub fn tensor_free(&self, id: u64) -> Result<(), GpuError> {
+        self.tensors.lock().unwrap().release(id)
+    }
+
+    pub(crate) fn header_buffer(&self, long: u32, width: u32) -> Result<PooledBuffer, GpuError> {
+        let header = MatrixHeader { long, width };
+        let contents = bytemuck::bytes_of(&header);
+        let buffer = self.acquire_buffer(
+            contents.len() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        )?;
+        self.queue.write_buffer(&buffer, 0, contents);
+        Ok(buffer)
+    }
+
+    /// dtype shared by the operands of a binary op
+    fn common_dtype(&self, a: u64, b: u64) -> Result<DType, GpuError> {
+        let dtype = self.tensor_dtype(a)?;
+        self.tensor(b, dtype)?;
+        Ok(dtype)
+    }
+
+    /// whether one of `lens` values of `dtype` is over the storage binding limit, such
+    /// matmuls go through the gemm, which tiles but only takes f32
+    fn needs_tiling(&self, lens: &[u64], dtype: DType) -> Result<bool, GpuError> {
+        if lens.iter().all(|len| len * dtype.size() <= self.binding_budget()) {
+            return Ok(false);
+        }
+        if dtype != DType::F32 {
+            return Err(GpuError::InvalidArgument(format!(
+                "{} matmul over the storage binding limit, only f32 is tiled",
+                dtype.name()
+            )));
+        }
+        Ok(true)
+    }
+
+    pub async fn tensor_add(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        self.tensor_elementwise(ElementwiseOp::Add, &[a, b], 0.0, 0.0).await
+    }
+
+    pub async fn tensor_matmul(&self, a: u64, b: u64) -> Result<u64, GpuError> {
+        let dtype = self.common_dtype(a, b)?;
+        let (buffer_a, shape_a) = self.tensor(a, dtype)?;
+        let (buffer_b, shape_b) = self.tensor(b, dtype)?;
+        let (a_long, a_width) = matrix_dims(&shape_a)?;
+        let (b_long, b_width) = matrix_dims(&shape_b)?;
+        if a_width != b_long {
+            return Err(GpuError::ShapeMismatch("Matrix A's width must equal Matrix B's height.".into()));
+        }
+        let lens = [a_long as u64 * a_width as u64, b_long as u64 * b_width as u64, a_long as u64 * b_width as u64];
+        if self.needs_tiling(&lens, dtype)? {
+            return self.tensor_gemm(&GemmSpec::new(a_long, b_width, a_width), a, b, None, None).await;
+        }
+
+        let kernel = self.typed_kernel("matrix_multiply", dtype)?;
+        let a_header = self.header_buffer(a_long, a_width)?;
+        let b_header = self.header_buffer(b_long, b_width)?;
+        let result = self.alloc_tensor(&[a_long, b_width], dtype)?;
+
+        self.dispatch(&kernel, 0, (b_width.div_ceil(16), a_long.div_ceil(16), 1), &[
+            wgpu::BindGroupEntry { binding: 0, resource: a_header.binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: b_header.binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: buffer_a.binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: buffer_b.binding() },
+            wgpu::BindGroupEntry { binding: 4, resource: result.binding() },
+        ]).await?;
+
+        Ok(self.tensors.lock().unwrap().insert(result, vec![a_long, b_width], dtype))
+    }
+
+    pub async fn tensor_vec_matmul(&self, v: u64, m: u64) -> Result<u64, GpuError> {
+        let dtype = self.common_dtype(v, m)?;
+        let (buffer_v, shape_v) = self.tensor(v, dtype)?;
+        let (buffer_m, shape_m) = self.tensor(m, dtype)?;
+        let (m_long, m_width) = matrix_dims(&shape_m)?;
+        if element_count(&shape_v)? != m_long {
+            return Err(GpuError::ShapeMismatch("Vector A's width must equal Matrix B's height.".into()));
+        }
+        // a matrix too large to bind is a single row gemm, which tiles
+        if self.needs_tiling(&[m_long as u64 * m_width as u64], dtype)? {
+            let spec = GemmSpec::new(1, m_width, m_long);
+            let out = self.gemm(&spec, &self.tensor_download(v).await?, &self.tensor_download(m).await?, None, None).await?;
+            return self.tensor_upload(&out, vec![m_width]);
+        }
+
+        let kernel = self.typed_kernel("vector_matrix_multiply", dtype)?;
+        let header = self.header_buffer(m_long, m_width)?;
+        let result = self.alloc_tensor(&[m_width], dtype)?;
+
+        self.dispatch(&kernel, 0, self.grid_1d(m_width, 64), &[
+            wgpu::BindGroupEntry { binding: 0, resource: buffer_v.binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: header.binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: buffer_m.binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: result.binding() },
+        ]).await?;
+
+        Ok(self.tensors.lock().unwrap().insert(result, vec![m_width], dtype))
+    }
+
+    /// f32 tensor of `(q - zero_point) * scale` for a u8 tensor `q`
+    pub async fn tensor_dequantize(&self, id: u64, scale: f32, zero_point: f32) -> Result<u64, GpuError> {
+        let (buffer, shape) = self.tensor(id, DType::U8)?;
+        let len = element_count(&shape)?;
+
+        let params = DequantizeParams { len, scale, zero_point, _pad: 0 };
+        let params_buffer = self.uniform_buffer(&params)?;
+        let result = self.alloc_tensor(&shape, DType::F32)?;
+
+        self.dispatch("dequantize_u8", 0, self.grid_1d(len, 64), &[
+            wgpu::BindGroupEntry { binding: 0, resource: params_buffer.binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: buffer.binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: result.binding() },
+        ]).await?;
+
+        Ok(self.tensors.lock().unwrap().insert(result, shape, DType::F32))
+    }
+}
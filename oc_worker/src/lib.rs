@@ -11,9 +11,31 @@ mod thread_keep_alive;
 mod thread_test;
 mod protocol;
 mod gpu_init;
+mod gpu_adapter;
+mod gpu_error;
+mod gpu_buffer_pool;
+mod gpu_tensor;
+mod gpu_batch;
+mod gpu_dtype;
+mod gpu_kernel;
+mod gpu_reflect;
+mod gpu_conv;
+mod gpu_elementwise;
+mod gpu_gemm;
+mod gpu_nn;
+mod gpu_pipeline_cache;
+mod gpu_profile;
+mod gpu_reduce;
+mod gpu_schedule;
 mod gpu_shade;
 mod gpu_func;
 mod gpu_init_rune_func;
+mod compute_backend;
+mod cpu_backend;
+mod capability;
+mod bench;
+mod result_hash;
+mod progress;
 
 define_global!(USER_TOKEN, String, String::new());
 define_static!(G_AUTH_CODE, u64, 0x24420251131_u64);
@@ -22,15 +44,23 @@ pub async fn sleep_ms(ms: u32) {
     TimeoutFuture::new(ms).await;
 }
 
+/// `options` is an optional json of `gpu_adapter::WorkerOptions`, e.g. `{"gpu": {"power": "low_power"}}`
 #[wasm_bindgen]
-pub fn worker_start(token : &str) -> Result<bool, JsValue>{
+pub fn worker_start(token : &str, options: Option<String>) -> Result<bool, JsValue>{
+
+    let options: gpu_adapter::WorkerOptions = match options {
+        Some(json) => public::parse_json(&json).map_err(|e| JsValue::from_str(&format!("bad worker options: {}", e)))?,
+        None => Default::default(),
+    };
 
     let mut token_ = USER_TOKEN.lock().unwrap();
     *token_ = token.to_string().clone();
 
+    progress::init_progress();
+
     WasmThreadManager::spawn_task(thread_ws_send::thread_ws_send(token.to_string(), config::WS_SERVER_URL.to_string()));
     WasmThreadManager::spawn_task(thread_keep_alive::heat_beat());
-    WasmThreadManager::spawn_task(thread_test::test_gpu());
+    WasmThreadManager::spawn_task(thread_test::test_gpu(options.gpu));
 
     //for debug log
     console_error_panic_hook::set_once();
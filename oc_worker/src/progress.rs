@@ -0,0 +1,100 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::protocol;
+use dynamic_code::{rune::Module, register_module};
+
+// at most one worker/progress per run in this window, start and finish always go out
+const PROGRESS_INTERVAL_MS: f64 = 1000.0;
+
+struct RunProgress {
+    op_id: u64,
+    last_sent_ms: Option<f64>,
+}
+
+thread_local! {
+    /// event_id -> throttling state of every run in flight
+    static RUNS: RefCell<HashMap<u64, RunProgress>> = RefCell::new(HashMap::new());
+    /// event_id of the run being polled right now, see `InRun`
+    static CURRENT_RUN: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+pub fn init_progress() {
+    register_module("report_progress", |module: &mut Module| {
+        module.function(["report_progress"], report_progress).build()?;
+        Ok(())
+    });
+}
+
+/// start tracking a run, its `report_progress` calls must come from inside `in_run`
+pub fn begin_run(event_id: u64, op_id: u64) {
+    RUNS.with(|runs| {
+        runs.borrow_mut().insert(event_id, RunProgress { op_id, last_sent_ms: None });
+    });
+}
+
+pub fn end_run(event_id: u64) {
+    RUNS.with(|runs| {
+        runs.borrow_mut().remove(&event_id);
+    });
+}
+
+/// `f` with `report_progress` tagged with `event_id` whenever it is polled,
+/// so runs interleaving on the executor each report as themselves
+pub fn in_run<F: Future>(event_id: u64, f: F) -> InRun<F> {
+    InRun { event_id, inner: Box::pin(f) }
+}
+
+pub struct InRun<F> {
+    event_id: u64,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for InRun<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let previous = CURRENT_RUN.with(|run| run.replace(Some(self.event_id)));
+        let poll = self.inner.as_mut().poll(cx);
+        CURRENT_RUN.with(|run| run.set(previous));
+        poll
+    }
+}
+
+/// event_id of the run being polled, `None` outside of `in_run`
+pub fn current_run() -> Option<u64> {
+    CURRENT_RUN.with(Cell::get)
+}
+
+/// called from rune scripts, `fraction` in 0.0..=1.0
+pub fn report_progress(fraction: f64, message: String) {
+    let fraction = fraction.clamp(0.0, 1.0);
+    let now = js_sys::Date::now();
+
+    let Some(event_id) = current_run() else {
+        return;
+    };
+    let op_id = RUNS.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        let run = runs.get_mut(&event_id)?;
+
+        let due = match run.last_sent_ms {
+            None => true,
+            Some(last) => fraction >= 1.0 || now - last >= PROGRESS_INTERVAL_MS,
+        };
+
+        if !due {
+            return None;
+        }
+
+        run.last_sent_ms = Some(now);
+        Some(run.op_id)
+    });
+
+    if let Some(op_id) = op_id {
+        protocol::worker_progress(event_id, op_id, fraction, message);
+    }
+}
@@ -4,6 +4,10 @@ use crate::thread_ws_send::send_msg_to_ws_server;
 use serde::{Deserialize, Serialize};
 use public::{encode, decode, build_json, parse_json, rand_u64};
 use crate::{G_AUTH_CODE};
+use crate::gpu_kernel::KernelSource;
+use crate::capability::capability_report;
+use crate::bench::BenchReport;
+use crate::gpu_profile::RunMetrics;
 
 #[derive(Deserialize, Serialize)]
 pub struct BaseMsg {
@@ -22,8 +26,8 @@ impl BaseMsg {
         let payload = encode(&msg, event_id);
 
         Self {
-            event_id: event_id,
-            payload: payload,
+            event_id,
+            payload,
             already_init: false,
             msg_info: msg,
         }
@@ -53,30 +57,63 @@ impl MsgInfo {
     pub fn new(operator_id_: u64, payload: String) -> Self {
         Self {
             operator_id: operator_id_,
-            payload: payload,
+            payload,
             auth_code: *G_AUTH_CODE,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RunCodePayload {
-    func: String,
-    input: String,
-    output: i16,
-}
-
 #[derive(Deserialize, Serialize)]
 struct RunCodeResult{
     operator_id : u64,
 	error	    : String,
 	result	    : String,
+	result_hash : String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	input_hash  : Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	script_hash : Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	metrics     : Option<RunMetrics>,
+}
+
+/// commitment sent along with a run result, `result_hash` is over the canonical json of the output
+#[derive(Debug, Clone, Default)]
+pub struct RunCodeHashes {
+    pub result_hash: String,
+    pub input_hash: Option<String>,
+    pub script_hash: Option<String>,
 }
 
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InitCodePayload {
     pub source_uid: String,
+    #[serde(default)]
+    pub deterministic: bool,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub kernels: Vec<KernelSource>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CancelPayload {
+    pub event_id: u64,
+}
+
+#[derive(Serialize)]
+struct StatusResult{
+    event_id: u64,
+    succ    : bool,
+    payload : String,
+}
+
+#[derive(Serialize)]
+struct ProgressResult{
+    operator_id : u64,
+    fraction    : f64,
+    message     : String,
 }
 
 #[derive(Serialize)]
@@ -86,6 +123,15 @@ struct InitCodeResult{
     payload : String,
 }
 
+/// a lost gpu device and whether a new one took its place
+#[derive(Serialize)]
+struct DeviceLostReport{
+    adapter: String,
+    reason: String,
+    recovered: bool,
+    error: String,
+}
+
 // pub fn direct_send_error_msg(other_msg: String) {
 //     let error_msg_s = BaseMsg::new(0, MsgInfo::new(0, other_msg));
 
@@ -127,28 +173,72 @@ pub fn send_big_payload_msg_to_verifier(event_id: u64, op_id: u64, route: String
 }
 
 pub fn worker_hello(){
-    send_msg_to_verifier("worker/hello".to_string(), "".to_string());
+    let report = build_json(&capability_report()).unwrap();
+
+    send_msg_to_verifier("worker/hello".to_string(), report);
 }
 
 
 
 pub fn worker_init(event_id: u64, source_uid: String, succ: bool, more_info: String){
 
-    let init_result = InitCodeResult{source_uid: source_uid, succ: succ, payload: more_info};
+    let init_result = InitCodeResult{source_uid, succ, payload: more_info};
 
     let result_payload = build_json(&init_result).unwrap();
 
     send_msg_to_verifier_by_event_id_op_id("worker/init".to_string(), event_id, 0, result_payload);
 }
 
-pub fn worker_run(event_id: u64, op_id: u64, source_uid: String, result: String, error: String){
-    let tmp_payload = RunCodeResult {operator_id: op_id, error: error, result: result};
+pub fn worker_run(event_id: u64, op_id: u64, source_uid: String, result: String, error: String, hashes: RunCodeHashes, metrics: Option<RunMetrics>){
+    let tmp_payload = RunCodeResult {
+        operator_id: op_id,
+        error,
+        result,
+        result_hash: hashes.result_hash,
+        input_hash: hashes.input_hash,
+        script_hash: hashes.script_hash,
+        metrics,
+    };
 
     let run_code_payload = build_json(&tmp_payload).unwrap();
 
     send_big_payload_msg_to_verifier(event_id, op_id, "worker/run".to_string(), source_uid, run_code_payload);
 }
 
-// pub fn worker_close(event_id: u64){
-//     send_msg_to_verifier_by_event_id_op_id("worker/close".to_string(), event_id, 0, "".to_string());
-// }
\ No newline at end of file
+pub fn worker_device_lost(adapter: String, reason: String, error: Option<String>){
+    let report = DeviceLostReport{adapter, reason, recovered: error.is_none(), error: error.unwrap_or_default()};
+
+    let report_payload = build_json(&report).unwrap();
+
+    send_msg_to_verifier("worker/device_lost".to_string(), report_payload);
+}
+
+pub fn worker_progress(event_id: u64, op_id: u64, fraction: f64, message: String){
+    let progress = ProgressResult{operator_id: op_id, fraction, message};
+
+    let progress_payload = build_json(&progress).unwrap();
+
+    send_msg_to_verifier_by_event_id_op_id("worker/progress".to_string(), event_id, op_id, progress_payload);
+}
+
+pub fn worker_cancel(event_id: u64, target_event_id: u64, succ: bool, more_info: String){
+    let cancel_result = StatusResult{event_id: target_event_id, succ, payload: more_info};
+
+    let result_payload = build_json(&cancel_result).unwrap();
+
+    send_msg_to_verifier_by_event_id_op_id("worker/cancel".to_string(), event_id, 0, result_payload);
+}
+
+pub fn worker_close(event_id: u64, succ: bool, more_info: String){
+    let close_result = StatusResult{event_id, succ, payload: more_info};
+
+    let result_payload = build_json(&close_result).unwrap();
+
+    send_msg_to_verifier_by_event_id_op_id("worker/close".to_string(), event_id, 0, result_payload);
+}
+
+pub fn worker_bench(event_id: u64, report: &BenchReport){
+    let report_payload = build_json(report).unwrap();
+
+    send_msg_to_verifier_by_event_id_op_id("worker/bench".to_string(), event_id, 0, report_payload);
+}
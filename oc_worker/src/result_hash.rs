@@ -0,0 +1,59 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// sha256 of `data` as lowercase hex
+pub fn sha256_hex(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// what a result may hold before it is hashed, json has no encoding for NaN and infinity
+/// and would write them as null, so such results are refused instead of colliding
+pub trait FiniteFloats {
+    fn all_finite(&self) -> bool;
+}
+
+impl FiniteFloats for i32 {
+    fn all_finite(&self) -> bool {
+        true
+    }
+}
+
+impl FiniteFloats for f32 {
+    fn all_finite(&self) -> bool {
+        self.is_finite()
+    }
+}
+
+impl FiniteFloats for String {
+    fn all_finite(&self) -> bool {
+        true
+    }
+}
+
+impl<T: FiniteFloats> FiniteFloats for Vec<T> {
+    fn all_finite(&self) -> bool {
+        self.iter().all(FiniteFloats::all_finite)
+    }
+}
+
+/// hash of the canonical json encoding of `value`,
+/// floats use the shortest round trip form so equal values always hash the same
+pub fn canonical_hash<T: Serialize + FiniteFloats>(value: &T) -> Result<String, String> {
+    if !value.all_finite() {
+        return Err("result contains NaN or infinity, which has no canonical encoding".to_string());
+    }
+    serde_json::to_vec(value)
+        .map(|bytes| sha256_hex(&bytes))
+        .map_err(|e| format!("result cannot be encoded for hashing: {}", e))
+}
+
+/// re-encode a json document with sorted keys and no whitespace before hashing,
+/// falls back to the raw bytes when it is not json
+pub fn canonical_json_hash(json_str: &str) -> String {
+    // parsed json holds no NaN or infinity and a `Value` always encodes
+    match serde_json::from_str::<serde_json::Value>(json_str).and_then(|value| serde_json::to_vec(&value)) {
+        Ok(bytes) => sha256_hex(&bytes),
+        Err(_) => sha256_hex(json_str.as_bytes()),
+    }
+}
@@ -14,6 +14,6 @@ pub async fn heat_beat(){
 	loop{
 		sleep_ms(60000).await;
 		worker_hello();
-		web_sys::console::log_1(&format!("heat beat").into());
+		web_sys::console::log_1(&"heat beat".into());
 	}
 }
\ No newline at end of file
@@ -1,5 +1,7 @@
 use crate::sleep_ms;
 use crate::gpu_init_rune_func::init_gpu;
+use crate::gpu_adapter::AdapterPolicy;
+use crate::{bench, protocol};
 // use crate::gpu_init_rune_func::get_gpu;
 
 // use crate::gpu_init::{GpuManager};
@@ -10,14 +12,16 @@ use crate::gpu_init_rune_func::init_gpu;
 //     js_sys::Date::now() as u64
 // }
 
-pub async fn test_gpu(){
+pub async fn test_gpu(policy: AdapterPolicy){
 
-    init_gpu().await;
+    init_gpu(&policy).await;
 
-	// let gpu = get_gpu();
+    // score this worker once the backend is up so the scheduler can weight it
+    let report = bench::run_bench().await;
+    log::info!("startup bench: {:?}", report);
+    protocol::worker_bench(public::rand_u64(), &report);
 
-    // let res = gpu.run_add_u32(3_u32, 3_u32).await.unwrap();
-    // web_sys::console::log_1(&format!("add Result: {:?}", res).into());
+	// let gpu = get_gpu();
 
     // let a: [f32; 2] = [3.0, 2.0];
     // let b: [f32; 2] = [4.0, 3.0];
@@ -12,9 +12,9 @@ struct WsClientMsg{
 
 pub fn send_msg_to_ws_server(route: String, payload: String, big_payload: String){
     let tmp = WsClientMsg{
-        route: route,
-        payload: payload,
-        big_payload: big_payload,
+        route,
+        payload,
+        big_payload,
     };
 
     send_msg::<WsClientMsg>(config::THREAD_WS_SEND, tmp);
@@ -28,14 +28,12 @@ pub async fn thread_ws_send(token: String, ip: String){
     ws.route_ws_big_payload("worker/init", client_process::worker_init);
     ws.route_ws_big_payload("worker/run", client_process::worker_run);
     ws.route_ws("worker/close", client_process::worker_close);
+    ws.route_ws("worker/cancel", client_process::worker_cancel);
+    ws.route_ws("worker/bench", client_process::worker_bench);
 
     ws.start_ws();
 
-    loop {
-        if let Some(msg) = recv_msg::<WsClientMsg>(config::THREAD_WS_SEND).await {
-            ws.send_big_payload(msg.route, msg.payload, msg.big_payload).await;
-        } else {
-            break;
-        }
+    while let Some(msg) = recv_msg::<WsClientMsg>(config::THREAD_WS_SEND).await {
+        ws.send_big_payload(msg.route, msg.payload, msg.big_payload).await;
     }
 }
\ No newline at end of file
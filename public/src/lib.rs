@@ -0,0 +1,34 @@
+// open build of the shared helpers: the deployed worker is linked against a private lib.rs
+// whose encode/decode match the verifier, this one keeps the same signatures so the
+// workspace builds, lints and tests without it
+include!("lib_fake");
+
+// keystream of the message key, the same key must be passed to decode
+fn xor_key_stream(data: &mut [u8], key: u64) {
+    let mut state = key;
+    for chunk in data.chunks_mut(8) {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        for (byte, k) in chunk.iter_mut().zip(z.to_le_bytes()) {
+            *byte ^= k;
+        }
+    }
+}
+
+pub fn encode<T: Serialize + ?Sized>(data: &T, key: u64) -> String {
+    let mut bytes = serde_json::to_vec(data).unwrap_or_default();
+    xor_key_stream(&mut bytes, key);
+    general_purpose::STANDARD.encode(bytes)
+}
+
+/// empty string when `data` was not produced by encode with the same key
+pub fn decode(data: &str, key: u64) -> String {
+    let Ok(mut bytes) = general_purpose::STANDARD.decode(data) else {
+        return String::new();
+    };
+    xor_key_stream(&mut bytes, key);
+    String::from_utf8(bytes).unwrap_or_default()
+}
@@ -117,7 +117,7 @@ impl WsClient {
                             if let Ok(parsed) = serde_json::from_str::<WsResponse>(json_str) {
                                 let big_payload = &text[json_end..];
 
-                                if big_payload.len() > 0{
+                                if !big_payload.is_empty(){
                                     let cb_opt = {
                                         let inner_ref = inner.borrow();
                                         inner_ref.routes_big_payload.get(&parsed.r).cloned()
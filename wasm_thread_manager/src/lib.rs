@@ -3,7 +3,8 @@ use std::future::Future;
 
 use once_cell::sync::Lazy;
 use futures::channel::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use futures::lock::Mutex;
 use futures::stream::StreamExt;
 
 const WASM_THREAD_COUNT: usize = 10;
@@ -42,18 +43,24 @@ impl<T: 'static> WasmChannelPool<T> {
     // async recv msg
     pub async fn recv(&self, thread_id: usize) -> Option<T> {
         let rx = self.channels[thread_id].rx.clone();
-        let mut rx = rx.lock().unwrap();
+        let mut rx = rx.lock().await;
         rx.next().await
     }
 
     // try to recv msg
     pub fn try_recv(&self, thread_id: usize) -> Option<T> {
         let rx = self.channels[thread_id].rx.clone();
-        let mut rx = rx.lock().unwrap();
+        let mut rx = rx.try_lock()?;
         rx.try_next().ok().flatten()
     }
 }
 
+impl<T: 'static> Default for WasmChannelPool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // use lazy confirm create once
 static WASM_CHANNEL_POOL: Lazy<WasmChannelPool<Box<dyn std::any::Any + Send>>> = Lazy::new(WasmChannelPool::new);
 